@@ -0,0 +1,3 @@
+drop extension if exists fuzzystrmatch;
+drop index if exists links_short_link_prefix;
+drop index if exists links_short_link_key;
@@ -0,0 +1,27 @@
+-- Short links are resolved case-insensitively, so they're stored in lowercase. Links that only
+-- differ in case can't both keep their name, and picking one would send the owners of the other
+-- somewhere else. Those have to be renamed or removed by hand before upgrading.
+do $$
+declare
+  collisions text;
+begin
+  select string_agg(names, '; ') into collisions
+  from (
+    select string_agg(short_link || ' (id ' || id || ')', ', ' order by id) as names
+    from links
+    group by lower(short_link)
+    having count(*) > 1
+  ) as groups;
+  if collisions is not null then
+    raise exception 'Short links that only differ in case have to be renamed first: %', collisions;
+  end if;
+end $$;
+
+update links set short_link = lower(short_link);
+
+create unique index links_short_link_key on links (short_link);
+-- Lets the "did you mean" suggestions look up names by their first characters.
+create index links_short_link_prefix on links (short_link text_pattern_ops);
+
+-- Provides levenshtein() for the "did you mean" suggestions.
+create extension if not exists fuzzystrmatch;
@@ -0,0 +1,4 @@
+alter table links
+  drop column management_token_hash,
+  drop column expires_at,
+  drop column redirect_type;
@@ -0,0 +1,9 @@
+alter table links
+  add column management_token_hash text,
+  add column expires_at timestamp,
+  add column redirect_type text not null default 'temporary';
+
+-- Links used to be cleaned up a week after they were created.
+update links set expires_at = created_at + interval '7 days';
+
+alter table links alter column expires_at set not null;
@@ -0,0 +1,2 @@
+alter table links drop column api_key_id;
+drop table api_keys;
@@ -0,0 +1,12 @@
+create table api_keys (
+  id serial primary key,
+  name text not null,
+  key_hash text not null unique,
+  daily_quota integer,
+  created_at timestamp not null default now(),
+  revoked_at timestamp
+);
+
+alter table links add column api_key_id integer references api_keys (id) on delete set null;
+
+create index links_api_key_id_idx on links (api_key_id);
@@ -0,0 +1 @@
+alter table links drop column visit_count;
@@ -0,0 +1 @@
+alter table links add column visit_count bigint not null default 0;
@@ -0,0 +1 @@
+alter table links drop column deleted_at;
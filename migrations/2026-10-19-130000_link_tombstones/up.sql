@@ -0,0 +1 @@
+alter table links add column deleted_at timestamp;
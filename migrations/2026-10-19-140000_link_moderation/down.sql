@@ -0,0 +1,4 @@
+drop table audit_log;
+drop table banned_domains;
+alter table links drop column disabled_at;
+alter table api_keys drop column is_admin;
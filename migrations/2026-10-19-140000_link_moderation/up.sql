@@ -0,0 +1,20 @@
+alter table api_keys add column is_admin boolean not null default false;
+
+alter table links add column disabled_at timestamp;
+
+create table banned_domains (
+  domain text primary key,
+  reason text,
+  created_at timestamp not null default now()
+);
+
+create table audit_log (
+  id serial primary key,
+  api_key_id integer references api_keys (id) on delete set null,
+  action text not null,
+  target text not null,
+  details text,
+  created_at timestamp not null default now()
+);
+
+create index audit_log_created_at_idx on audit_log (created_at);
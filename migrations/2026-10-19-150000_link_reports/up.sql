@@ -0,0 +1,15 @@
+create table link_reports (
+  id serial primary key,
+  link_id integer not null references links (id) on delete cascade,
+  reason text not null,
+  details text,
+  reporter_hash text not null,
+  reporter_agent text,
+  api_key_id integer references api_keys (id) on delete set null,
+  created_at timestamp not null default now(),
+  resolved_at timestamp
+);
+
+-- A reporter counts once per link until their report is resolved.
+create unique index link_reports_open_reporter_idx on link_reports (link_id, reporter_hash)
+  where resolved_at is null;
@@ -0,0 +1 @@
+alter table links add column interstitial boolean not null default false;
@@ -0,0 +1 @@
+alter table links drop column password_hash;
@@ -0,0 +1 @@
+alter table links add column remaining_visits integer check (remaining_visits >= 0);
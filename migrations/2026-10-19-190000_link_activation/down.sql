@@ -0,0 +1 @@
+alter table links drop column not_before;
@@ -0,0 +1 @@
+alter table links add column not_before timestamp;
@@ -0,0 +1,15 @@
+-- Rules are evaluated by position, the first one whose conditions all match picks the
+-- destination. Links fall back to their own destination when none matches.
+create table link_rules (
+  id serial primary key,
+  link_id integer not null references links (id) on delete cascade,
+  position integer not null,
+  platform text,
+  language text,
+  country text,
+  -- Minutes since midnight UTC, the window wraps around midnight when it ends before it starts.
+  starts_at_minute integer,
+  ends_at_minute integer,
+  destination text not null,
+  unique (link_id, position)
+);
@@ -0,0 +1,2 @@
+alter table links drop column sticky_variants;
+drop table link_variants;
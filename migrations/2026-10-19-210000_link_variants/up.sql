@@ -0,0 +1,14 @@
+-- Visitors that match no routing rule are split between the variants of a link by weight. Each
+-- variant counts the visits it got, so both sides of a split can be compared.
+create table link_variants (
+  id serial primary key,
+  link_id integer not null references links (id) on delete cascade,
+  position integer not null,
+  destination text not null,
+  weight integer not null check (weight > 0),
+  visit_count bigint not null default 0,
+  unique (link_id, position)
+);
+
+-- Returning visitors get the variant they saw before.
+alter table links add column sticky_variants boolean not null default false;
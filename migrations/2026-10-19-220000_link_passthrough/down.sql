@@ -0,0 +1,2 @@
+alter table links drop column forward_path;
+alter table links drop column forward_query;
@@ -0,0 +1,3 @@
+-- Whether the query string and the path after the name are passed on to the destination.
+alter table links add column forward_query boolean not null default false;
+alter table links add column forward_path boolean not null default false;
@@ -0,0 +1,180 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    config::{AppState, DBConfig},
+    errors::AppError,
+    generator::{
+        database::{ApiKey, ApiKeysRepository, NewApiKey, PostgresRepository},
+        token::{generate_token, hash_token},
+    },
+    rate_limit::{client_identity, ensure_attempts_left, limit_attempts},
+};
+
+const ONE_DAY: Duration = Duration::from_secs(86400);
+
+/// The API key a request was made with, if any. Keys are validated by the `authenticate` layer
+/// around the API, link resolution isn't behind it so following links works whatever
+/// `Authorization` header a browser sends along.
+#[derive(Clone)]
+pub struct Caller(pub Option<ApiKey>);
+
+/// Hands handlers the caller `authenticate` found. Routes outside the layer fail loudly rather
+/// than treating every request as anonymous.
+#[async_trait]
+impl<S> FromRequestParts<S> for Caller
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Caller>().cloned().ok_or_else(|| {
+            AppError::InfraError("The route isn't behind the authentication layer.".to_string())
+        })
+    }
+}
+
+/// Validates the `Authorization: Bearer <key>` header when one is present and leaves the
+/// `Caller` in the request extensions. Requests without the header are anonymous, unknown or
+/// revoked keys are rejected. Clients that keep sending unknown keys are turned away before
+/// their keys are looked up.
+pub async fn authenticate(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let caller = match bearer_token(request.headers()) {
+        Some(token) => Caller(Some(find_api_key(&state, &request, token)?)),
+        None => Caller(None),
+    };
+    request.extensions_mut().insert(caller);
+    Ok(next.run(request).await)
+}
+
+fn find_api_key(state: &AppState, request: &Request, token: &str) -> Result<ApiKey, AppError> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    let trusted_proxies = &state.app_config.trusted_proxies;
+    let client = client_identity(None, peer, request.headers(), trusted_proxies);
+    ensure_attempts_left(&state.auth_limiter, &client)?;
+    let mut repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    match repo.find_api_key(&hash_token(token))? {
+        Some(api_key) => Ok(api_key),
+        None => {
+            limit_attempts(&state.auth_limiter, &client)?;
+            Err(AppError::UnauthorizedError)
+        }
+    }
+}
+
+/// Only lets requests made with an admin key through. Goes inside `authenticate`.
+pub async fn require_admin(request: Request, next: Next) -> Result<Response, AppError> {
+    match request.extensions().get::<Caller>() {
+        Some(caller) => caller.require_admin()?,
+        None => return Err(AppError::UnauthorizedError),
+    };
+    Ok(next.run(request).await)
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let header = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    header.strip_prefix("Bearer ").map(str::trim)
+}
+
+impl Caller {
+    /// Returns the key that must be attached to new links, rejecting anonymous callers when keys
+    /// are required. The daily quota is checked when the links are stored.
+    pub fn authorize_shorten(&self, require_api_key: bool) -> Result<Option<i32>, AppError> {
+        match &self.0 {
+            Some(api_key) => Ok(Some(api_key.id)),
+            None if require_api_key => Err(AppError::UnauthorizedError),
+            None => Ok(None),
+        }
+    }
+
+    pub fn require_key(&self) -> Result<&ApiKey, AppError> {
+        self.0.as_ref().ok_or(AppError::UnauthorizedError)
+    }
+
+    /// Only admin keys may use the moderation API.
+    pub fn require_admin(&self) -> Result<&ApiKey, AppError> {
+        let api_key = self.require_key()?;
+        if !api_key.is_admin {
+            return Err(AppError::ForbiddenError);
+        }
+        Ok(api_key)
+    }
+}
+
+/// Returns the start of the UTC day `now` falls in and the time left until the next one.
+/// Quotas are counted per calendar day in UTC.
+pub(crate) fn day_bounds(now: SystemTime) -> (SystemTime, Duration) {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let into_day = Duration::from_secs(since_epoch.as_secs() % ONE_DAY.as_secs());
+    (now - into_day, ONE_DAY - into_day)
+}
+
+/// Creates a new key and prints it. The key itself is only stored as a hash, so this is the
+/// only time it can be seen.
+pub fn create_api_key(db_config: &DBConfig, name: &str, daily_quota: Option<i32>, is_admin: bool) {
+    let mut rng = rand::thread_rng();
+    let key = generate_token(&mut rng);
+    let new_key = NewApiKey {
+        name,
+        key_hash: &hash_token(&key),
+        daily_quota,
+        is_admin,
+    };
+    let mut repo = PostgresRepository::from_config(db_config)
+        .expect("An error occurred when trying to obtain a database connection.");
+    let api_key = repo
+        .create_api_key(&new_key)
+        .expect("An error occurred while storing the API key.");
+    println!(
+        "Created API key {} for '{}': {key}",
+        api_key.id, api_key.name
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_day_bounds() {
+        let now = UNIX_EPOCH + Duration::from_secs(3 * 86400 + 3600);
+        let (start, remaining) = day_bounds(now);
+        assert_eq!(start, UNIX_EPOCH + Duration::from_secs(3 * 86400));
+        assert_eq!(remaining, Duration::from_secs(86400 - 3600));
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, "Bearer abc123".parse().unwrap());
+        assert_eq!(bearer_token(&headers), Some("abc123"));
+    }
+
+    #[tokio::test]
+    async fn test_caller_needs_the_authentication_layer() {
+        let (mut parts, _) = Request::new(()).into_parts();
+        let result = Caller::from_request_parts(&mut parts, &()).await;
+        assert!(matches!(result, Err(AppError::InfraError(_))));
+        parts.extensions.insert(Caller(None));
+        let caller = Caller::from_request_parts(&mut parts, &()).await.unwrap();
+        assert!(caller.0.is_none());
+    }
+}
@@ -13,7 +13,7 @@ pub async fn spawn_cleanup_task(db_config: Arc<AppState>) {
             continue;
         };
 
-        let result = repo.cleanup_old_links();
+        let result = repo.cleanup_old_links(db_config.app_config.tombstone_grace);
         match result {
             Ok(res) => log::info!("Cleaned up {res} old links."),
             Err(e) => log::error!("The following error occurred while doing db cleanup: {e}"),
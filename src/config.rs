@@ -1,20 +1,114 @@
+use crate::generator::moderation::domain_suffixes;
 use crate::generator::name_generator::NameGenerator;
+use crate::generator::shorten::{DEFAULT_TTL, MAX_TTL};
+use crate::generator::token::generate_token;
+use crate::geoip::GeoIp;
+use crate::pages::{load_template, MISSING_LINK_TEMPLATE};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use axum::http::{HeaderName, HeaderValue, Method};
+use ipnet::IpNet;
 use std::env;
+use std::net::IpAddr;
+use std::time::Duration;
+use url::Url;
+
+pub const DEFAULT_DENY_LIST_PATH: &str = "data/denylist.txt";
+const DEFAULT_TOMBSTONE_GRACE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+const DEFAULT_SHORTEN_RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 10,
+    burst: 5,
+};
+const DEFAULT_RESOLVE_RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 120,
+    burst: 60,
+};
+const DEFAULT_PASSWORD_RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 5,
+    burst: 5,
+};
+const DEFAULT_PASSWORD_LINK_RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 100,
+    burst: 100,
+};
+const DEFAULT_AUTH_RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 10,
+    burst: 10,
+};
 
 pub struct AppState {
     pub app_config: AppConfig,
     pub name_generator: NameGenerator,
+    pub shorten_limiter: RateLimiter,
+    pub resolve_limiter: RateLimiter,
+    /// Limits the wrong passwords per link and client.
+    pub password_limiter: RateLimiter,
+    /// Limits the wrong passwords per link, whoever sends them.
+    pub password_link_limiter: RateLimiter,
+    /// Limits the unknown API keys a client may send.
+    pub auth_limiter: RateLimiter,
+    pub missing_link_template: String,
+    pub geoip: Option<GeoIp>,
 }
 
 impl AppState {
     pub fn new(app_config: AppConfig, name_generator: NameGenerator) -> Self {
+        let shorten_limiter = RateLimiter::new(app_config.shorten_rate_limit);
+        let resolve_limiter = RateLimiter::new(app_config.resolve_rate_limit);
+        let password_limiter = RateLimiter::new(app_config.password_rate_limit);
+        let password_link_limiter = RateLimiter::new(app_config.password_link_rate_limit);
+        let auth_limiter = RateLimiter::new(app_config.auth_rate_limit);
+        let missing_link_template = load_template(
+            app_config.missing_link_template_path.as_deref(),
+            MISSING_LINK_TEMPLATE,
+        );
+        let geoip = app_config
+            .geoip_database_path
+            .as_deref()
+            .and_then(GeoIp::open);
         Self {
             app_config,
             name_generator,
+            shorten_limiter,
+            resolve_limiter,
+            password_limiter,
+            password_link_limiter,
+            auth_limiter,
+            missing_link_template,
+            geoip,
         }
     }
 }
 
+/// Which browser frontends on other origins may call the API. CORS headers are only added when
+/// at least one origin is allowed.
+pub struct CorsConfig {
+    pub allow_any_origin: bool,
+    pub allowed_origins: Vec<HeaderValue>,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<HeaderName>,
+    pub max_age: Duration,
+    pub allow_credentials: bool,
+}
+
+/// When visitors see a page with the destination instead of being redirected right away.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum InterstitialMode {
+    /// Only for links created with the interstitial option.
+    #[default]
+    Off,
+    /// For every link.
+    Always,
+    /// For links whose destination isn't on the allowlist.
+    Untrusted,
+}
+
+pub struct InterstitialConfig {
+    pub mode: InterstitialMode,
+    /// Trusted domains, each of them includes its subdomains.
+    pub allowlist: Vec<String>,
+}
+
 #[cfg_attr(test, derive(PartialEq))]
 #[derive(Debug)]
 pub struct DBConfig {
@@ -28,6 +122,46 @@ pub struct DBConfig {
 pub struct AppConfig {
     pub base_url: String,
     pub app_port: String,
+    pub deny_list_path: String,
+    pub suggest_similar_links: bool,
+    pub default_ttl: Duration,
+    pub max_ttl: Duration,
+    /// How long expired and deleted links keep their name reserved before they're removed.
+    pub tombstone_grace: Duration,
+    /// When set, only requests with a valid API key may create links.
+    pub require_api_key: bool,
+    /// `None` means the requests aren't limited.
+    pub shorten_rate_limit: Option<RateLimit>,
+    pub resolve_rate_limit: Option<RateLimit>,
+    /// How often a single client may get the password of a link wrong.
+    pub password_rate_limit: Option<RateLimit>,
+    /// How often the password of a link may be wrong in total. Much higher than the limit per
+    /// client, so one client can't lock everyone else out.
+    pub password_link_rate_limit: Option<RateLimit>,
+    /// How often a single client may send an unknown API key.
+    pub auth_rate_limit: Option<RateLimit>,
+    /// Proxies whose `X-Forwarded-For` header is trusted to contain the client's address.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Replaces the built-in page shown for links that don't exist or expired.
+    pub missing_link_template_path: Option<String>,
+    /// When set, visitors of missing links are redirected here instead of seeing the page.
+    pub missing_link_redirect: Option<Url>,
+    /// A MaxMind database used to match routing rules on the visitor's country.
+    pub geoip_database_path: Option<String>,
+    /// Serves Swagger UI for the OpenAPI spec at `/docs`.
+    pub api_docs_ui: bool,
+    /// Serves a small web UI for shortening and looking up links at `/`.
+    pub web_ui: bool,
+    /// Links are disabled once this many different clients reported them, `None` leaves
+    /// reports to the moderators.
+    pub report_disable_threshold: Option<u32>,
+    /// Reporters are stored as a keyed hash with this secret, so they can't be looked up by IP.
+    pub reporter_secret: String,
+    /// Signs the passes that let visitors of a protected link continue from the interstitial
+    /// page without sending the password again.
+    pub pass_secret: String,
+    pub cors: CorsConfig,
+    pub interstitial: InterstitialConfig,
     pub db_config: DBConfig,
 }
 
@@ -51,8 +185,37 @@ impl AppConfig {
     fn from_env() -> Self {
         let base_url_key_name = "BASE_URL";
         let app_port_key_name = "APP_PORT";
+        let deny_list_key_name = "DENY_LIST_PATH";
         let base_url = read_key(base_url_key_name);
         let app_port = read_key(app_port_key_name);
+        let suggest_key_name = "SUGGEST_SIMILAR_LINKS";
+        let deny_list_path = read_key_or(deny_list_key_name, DEFAULT_DENY_LIST_PATH);
+        let suggest_similar_links = read_flag(suggest_key_name);
+        let default_ttl = read_days_or("DEFAULT_TTL_DAYS", DEFAULT_TTL);
+        let max_ttl = read_days_or("MAX_TTL_DAYS", MAX_TTL);
+        let tombstone_grace = read_days_or("TOMBSTONE_GRACE_DAYS", DEFAULT_TOMBSTONE_GRACE);
+        let require_api_key = read_flag("REQUIRE_API_KEY");
+        let shorten_rate_limit = read_rate_limit("SHORTEN_RATE_LIMIT", DEFAULT_SHORTEN_RATE_LIMIT);
+        let resolve_rate_limit = read_rate_limit("RESOLVE_RATE_LIMIT", DEFAULT_RESOLVE_RATE_LIMIT);
+        let password_rate_limit =
+            read_rate_limit("PASSWORD_RATE_LIMIT", DEFAULT_PASSWORD_RATE_LIMIT);
+        let password_link_rate_limit =
+            read_rate_limit("PASSWORD_LINK_RATE_LIMIT", DEFAULT_PASSWORD_LINK_RATE_LIMIT);
+        let auth_rate_limit = read_rate_limit("AUTH_RATE_LIMIT", DEFAULT_AUTH_RATE_LIMIT);
+        let trusted_proxies = read_trusted_proxies("TRUSTED_PROXIES");
+        let missing_link_template_path = env::var("MISSING_LINK_TEMPLATE").ok();
+        let missing_link_redirect = read_url("MISSING_LINK_REDIRECT_URL");
+        let geoip_database_path = env::var("GEOIP_DATABASE_PATH").ok();
+        let api_docs_ui = read_flag("API_DOCS_UI");
+        let web_ui = read_flag("WEB_UI");
+        let report_disable_threshold = read_threshold("REPORT_DISABLE_THRESHOLD");
+        let reporter_secret = read_secret("REPORTER_SECRET");
+        let pass_secret = read_secret("PASS_SECRET");
+        let cors = CorsConfig::from_env();
+        let interstitial = InterstitialConfig::from_env();
+        if default_ttl > max_ttl {
+            panic!("DEFAULT_TTL_DAYS can't be larger than MAX_TTL_DAYS.")
+        };
         let db_config = DBConfig::from_env();
         if !base_url.ends_with('/') {
             panic!("The base URL must end with a slash '/' for the application to work correctly.")
@@ -61,6 +224,28 @@ impl AppConfig {
         AppConfig {
             base_url,
             app_port,
+            deny_list_path,
+            suggest_similar_links,
+            default_ttl,
+            max_ttl,
+            tombstone_grace,
+            require_api_key,
+            shorten_rate_limit,
+            resolve_rate_limit,
+            password_rate_limit,
+            password_link_rate_limit,
+            auth_rate_limit,
+            trusted_proxies,
+            missing_link_template_path,
+            missing_link_redirect,
+            geoip_database_path,
+            api_docs_ui,
+            web_ui,
+            report_disable_threshold,
+            reporter_secret,
+            pass_secret,
+            cors,
+            interstitial,
             db_config,
         }
     }
@@ -69,11 +254,138 @@ impl AppConfig {
         AppConfig {
             base_url: "http://localhost:8000/".to_string(),
             app_port: "8000".to_string(),
+            deny_list_path: DEFAULT_DENY_LIST_PATH.to_string(),
+            suggest_similar_links: false,
+            default_ttl: DEFAULT_TTL,
+            max_ttl: MAX_TTL,
+            tombstone_grace: DEFAULT_TOMBSTONE_GRACE,
+            require_api_key: false,
+            shorten_rate_limit: Some(DEFAULT_SHORTEN_RATE_LIMIT),
+            resolve_rate_limit: Some(DEFAULT_RESOLVE_RATE_LIMIT),
+            password_rate_limit: Some(DEFAULT_PASSWORD_RATE_LIMIT),
+            password_link_rate_limit: Some(DEFAULT_PASSWORD_LINK_RATE_LIMIT),
+            auth_rate_limit: Some(DEFAULT_AUTH_RATE_LIMIT),
+            trusted_proxies: Vec::new(),
+            missing_link_template_path: None,
+            missing_link_redirect: None,
+            geoip_database_path: None,
+            api_docs_ui: false,
+            web_ui: false,
+            report_disable_threshold: None,
+            reporter_secret: generate_token(&mut rand::thread_rng()),
+            pass_secret: generate_token(&mut rand::thread_rng()),
+            cors: CorsConfig::new(),
+            interstitial: InterstitialConfig::new(),
             db_config: DBConfig::new(),
         }
     }
 }
 
+const DEFAULT_CORS_METHODS: &str = "GET,POST,PATCH,DELETE";
+const DEFAULT_CORS_HEADERS: &str = "content-type,authorization,x-management-token";
+const DEFAULT_CORS_MAX_AGE: Duration = Duration::from_secs(3600);
+
+impl CorsConfig {
+    fn from_env() -> Self {
+        let origins_key_name = "CORS_ALLOWED_ORIGINS";
+        let origins = read_list(origins_key_name, "");
+        let allow_any_origin = origins.iter().any(|origin| origin == "*");
+        let allowed_origins = origins
+            .iter()
+            .filter(|origin| *origin != "*")
+            .map(|origin| parse_or_panic(origins_key_name, origin))
+            .collect();
+        let methods_key_name = "CORS_ALLOWED_METHODS";
+        let allowed_methods = read_list(methods_key_name, DEFAULT_CORS_METHODS)
+            .iter()
+            .map(|method| parse_or_panic(methods_key_name, &method.to_uppercase()))
+            .collect();
+        let headers_key_name = "CORS_ALLOWED_HEADERS";
+        let allowed_headers = read_list(headers_key_name, DEFAULT_CORS_HEADERS)
+            .iter()
+            .map(|header| parse_or_panic(headers_key_name, header))
+            .collect();
+        let max_age = match env::var("CORS_MAX_AGE_SECONDS") {
+            Ok(value) => Duration::from_secs(
+                value
+                    .parse()
+                    .expect("CORS_MAX_AGE_SECONDS must be a whole number of seconds."),
+            ),
+            Err(_) => DEFAULT_CORS_MAX_AGE,
+        };
+        let allow_credentials = read_flag("CORS_ALLOW_CREDENTIALS");
+        if allow_credentials && allow_any_origin {
+            panic!("CORS_ALLOW_CREDENTIALS can't be combined with allowing every origin ('*').")
+        }
+
+        CorsConfig {
+            allow_any_origin,
+            allowed_origins,
+            allowed_methods,
+            allowed_headers,
+            max_age,
+            allow_credentials,
+        }
+    }
+
+    fn new() -> Self {
+        CorsConfig {
+            allow_any_origin: false,
+            allowed_origins: Vec::new(),
+            allowed_methods: Vec::new(),
+            allowed_headers: Vec::new(),
+            max_age: DEFAULT_CORS_MAX_AGE,
+            allow_credentials: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.allow_any_origin || !self.allowed_origins.is_empty()
+    }
+}
+
+impl InterstitialConfig {
+    fn from_env() -> Self {
+        let mode_key_name = "INTERSTITIAL_MODE";
+        let mode = match read_key_or(mode_key_name, "off").to_lowercase().as_str() {
+            "off" => InterstitialMode::Off,
+            "always" => InterstitialMode::Always,
+            "untrusted" => InterstitialMode::Untrusted,
+            other => panic!("{mode_key_name} must be off, always or untrusted, not {other}."),
+        };
+        let allowlist = read_list("INTERSTITIAL_ALLOWLIST", "")
+            .iter()
+            .map(|domain| domain.trim_start_matches("*.").to_lowercase())
+            .collect();
+        InterstitialConfig { mode, allowlist }
+    }
+
+    fn new() -> Self {
+        InterstitialConfig {
+            mode: InterstitialMode::Off,
+            allowlist: Vec::new(),
+        }
+    }
+
+    /// Whether visitors of a link to `destination` see the interstitial page. `per_link` is
+    /// the option the link was created with, which always applies.
+    pub fn applies_to(&self, destination: &str, per_link: bool) -> bool {
+        match self.mode {
+            _ if per_link => true,
+            InterstitialMode::Off => false,
+            InterstitialMode::Always => true,
+            InterstitialMode::Untrusted => !Url::parse(destination)
+                .ok()
+                .and_then(|url| url.host_str().map(domain_suffixes))
+                .is_some_and(|suffixes| {
+                    suffixes
+                        .iter()
+                        .any(|suffix| self.allowlist.contains(suffix))
+                }),
+        }
+    }
+}
+
 impl DBConfig {
     fn from_env() -> Self {
         let user_key_name = "POSTGRES_USER";
@@ -127,6 +439,104 @@ fn read_key(key: &str) -> String {
     env::var(key).expect(&error_msg)
 }
 
+/// Reads an optional key, falling back to `default` when it isn't set.
+fn read_key_or(key: &str, default: &str) -> String {
+    env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+/// Reads an optional number of days, falling back to `default` when it isn't set.
+fn read_days_or(key: &str, default: Duration) -> Duration {
+    match env::var(key) {
+        Ok(value) => {
+            let days: u64 = value
+                .parse()
+                .unwrap_or_else(|_| panic!("{key} must be a whole number of days."));
+            Duration::from_secs(days * 24 * 60 * 60)
+        }
+        Err(_) => default,
+    }
+}
+
+/// Reads the `<KEY>_PER_MINUTE` and `<KEY>_BURST` pair of a rate limit. A rate of 0 turns
+/// the limit off, a missing burst defaults to the rate.
+fn read_rate_limit(key: &str, default: RateLimit) -> Option<RateLimit> {
+    let read_number = |key: String| {
+        env::var(&key).ok().map(|value| {
+            value
+                .parse::<u32>()
+                .unwrap_or_else(|_| panic!("{key} must be a whole number."))
+        })
+    };
+    let Some(per_minute) = read_number(format!("{key}_PER_MINUTE")) else {
+        return Some(default);
+    };
+    let burst = read_number(format!("{key}_BURST")).unwrap_or(per_minute);
+    (per_minute > 0).then_some(RateLimit { per_minute, burst })
+}
+
+/// Reads the number of reports after which a link is disabled, 0 or no value turns this off.
+fn read_threshold(key: &str) -> Option<u32> {
+    let Ok(value) = env::var(key) else {
+        return None;
+    };
+    let threshold: u32 = value
+        .parse()
+        .unwrap_or_else(|_| panic!("{key} must be a whole number."));
+    (threshold > 0).then_some(threshold)
+}
+
+/// Reads a secret, making up one for this run when it isn't set. Hashes made with a made up
+/// secret can't be compared with those of earlier runs.
+fn read_secret(key: &str) -> String {
+    env::var(key).unwrap_or_else(|_| {
+        log::warn!("{key} isn't set, a random secret is used until the app restarts.");
+        generate_token(&mut rand::thread_rng())
+    })
+}
+
+/// Reads a comma separated list, falling back to `default` when it isn't set.
+fn read_list(key: &str, default: &str) -> Vec<String> {
+    read_key_or(key, default)
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_or_panic<T: std::str::FromStr>(key: &str, entry: &str) -> T {
+    entry
+        .parse()
+        .unwrap_or_else(|_| panic!("{key} contains an invalid entry: {entry}"))
+}
+
+/// Reads a comma separated list of IP addresses and CIDR ranges.
+fn read_trusted_proxies(key: &str) -> Vec<IpNet> {
+    read_key_or(key, "")
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .unwrap_or_else(|_| panic!("{key} contains an invalid address: {entry}"))
+        })
+        .collect()
+}
+
+/// Reads an optional absolute URL.
+fn read_url(key: &str) -> Option<Url> {
+    let value = env::var(key).ok()?;
+    let url = Url::parse(&value).unwrap_or_else(|_| panic!("{key} must be an absolute URL."));
+    Some(url)
+}
+
+/// Reads an optional on/off switch. Anything other than `true` or `1` counts as off.
+fn read_flag(key: &str) -> bool {
+    env::var(key).is_ok_and(|value| matches!(value.to_lowercase().as_str(), "true" | "1"))
+}
+
 impl Default for DBConfig {
     /// Constructs a new DBConfig instance by reading the .env file.
     /// If the file is not found, it will use the default values. This is intentionally done
@@ -155,4 +565,21 @@ mod tests {
         let db_config = DBConfig::default();
         assert_eq!(app_config.db_config, db_config);
     }
+
+    #[test]
+    fn test_interstitial_applies_to_destinations_off_the_allowlist() {
+        let config = InterstitialConfig {
+            mode: InterstitialMode::Untrusted,
+            allowlist: vec!["example.com".to_string()],
+        };
+        assert!(!config.applies_to("https://example.com/a", false));
+        assert!(!config.applies_to("https://docs.EXAMPLE.com/a", false));
+        assert!(config.applies_to("https://example.com.evil.test/a", false));
+        assert!(config.applies_to("https://notexample.com/a", false));
+        assert!(config.applies_to("https://example.com/a", true));
+
+        let off = InterstitialConfig::new();
+        assert!(!off.applies_to("https://evil.test/", false));
+        assert!(off.applies_to("https://evil.test/", true));
+    }
 }
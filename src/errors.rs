@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
 };
 
@@ -8,9 +8,31 @@ pub enum AppError {
     DatabaseError(diesel::result::Error),
     UserInputError(String),
     NotFoundError,
+    /// The resource existed, but it expired or was removed. Holds an explanation for the user.
+    GoneError(String),
+    /// The link was disabled, because it was reported or by a moderator.
+    DisabledError,
+    /// The link was scheduled to start working later. Holds an explanation for the user.
+    NotYetActiveError(String),
+    /// The link exists, but it doesn't forward paths or the path isn't allowed.
+    UnknownPathError,
+    /// The link is behind a password, which has to be entered to follow it.
+    PasswordRequiredError,
+    WrongPasswordError,
+    UnauthorizedError,
+    ForbiddenError,
+    UnsupportedMediaTypeError,
+    /// Holds the number of seconds after which the client may try again.
+    TooManyRequestsError(u64),
     InfraError(String),
 }
 
+impl From<diesel::result::Error> for AppError {
+    fn from(error: diesel::result::Error) -> Self {
+        AppError::DatabaseError(error)
+    }
+}
+
 impl AppError {
     fn log_error(&self) {
         match self {
@@ -22,19 +44,56 @@ impl AppError {
 }
 
 const NOT_FOUND_ERR_MSG: & str =
-    "The resource you're looking for can't be found. Maybe it was already deleted? Links only stay valid for 7 days.";
+    "The resource you're looking for can't be found. Maybe it was already deleted? Links only stay valid for a limited time.";
+const DISABLED_ERR_MSG: &str =
+    "This link was disabled because it may lead to a harmful site, such as a phishing or malware page.";
+const UNKNOWN_PATH_ERR_MSG: &str =
+    "This short link exists, but it doesn't lead anywhere with the path after its name.";
+const PASSWORD_REQUIRED_ERR_MSG: &str = "This link is protected by a password.";
+const WRONG_PASSWORD_ERR_MSG: &str = "The password is wrong.";
+const UNAUTHORIZED_ERR_MSG: &str = "You need to supply a token to do this.";
+const FORBIDDEN_ERR_MSG: &str = "The token you supplied doesn't grant access to this resource.";
+const UNSUPPORTED_MEDIA_TYPE_ERR_MSG: &str =
+    "Send the link as JSON, as a form (application/x-www-form-urlencoded) or as plain text.";
+const TOO_MANY_REQUESTS_ERR_MSG: &str =
+    "You've made too many requests. Please wait a while before trying again.";
 const DB_ERR_MSG: &str =
     "An unexpected error occurred. If this persists please reach out and let me know.";
-const INPUT_ERR_MSG: & str  = "Something went wrong while trying to read your input. Is it a valid uri? (it must start with http:// or https://)";
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         self.log_error();
         let res = match self {
-            AppError::NotFoundError => (StatusCode::NOT_FOUND, NOT_FOUND_ERR_MSG),
-            AppError::DatabaseError(_) => (StatusCode::INTERNAL_SERVER_ERROR, DB_ERR_MSG),
-            AppError::UserInputError(_) => (StatusCode::BAD_REQUEST, INPUT_ERR_MSG),
-            AppError::InfraError(_) => (StatusCode::INTERNAL_SERVER_ERROR, DB_ERR_MSG),
+            AppError::NotFoundError => (StatusCode::NOT_FOUND, NOT_FOUND_ERR_MSG.to_string()),
+            AppError::GoneError(msg) => (StatusCode::GONE, msg),
+            AppError::DisabledError => (StatusCode::GONE, DISABLED_ERR_MSG.to_string()),
+            AppError::NotYetActiveError(msg) => (StatusCode::FORBIDDEN, msg),
+            AppError::UnknownPathError => (StatusCode::NOT_FOUND, UNKNOWN_PATH_ERR_MSG.to_string()),
+            AppError::DatabaseError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, DB_ERR_MSG.to_string())
+            }
+            AppError::UserInputError(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::PasswordRequiredError => (
+                StatusCode::UNAUTHORIZED,
+                PASSWORD_REQUIRED_ERR_MSG.to_string(),
+            ),
+            AppError::WrongPasswordError => {
+                (StatusCode::FORBIDDEN, WRONG_PASSWORD_ERR_MSG.to_string())
+            }
+            AppError::UnauthorizedError => {
+                (StatusCode::UNAUTHORIZED, UNAUTHORIZED_ERR_MSG.to_string())
+            }
+            AppError::ForbiddenError => (StatusCode::FORBIDDEN, FORBIDDEN_ERR_MSG.to_string()),
+            AppError::UnsupportedMediaTypeError => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                UNSUPPORTED_MEDIA_TYPE_ERR_MSG.to_string(),
+            ),
+            AppError::TooManyRequestsError(retry_after) => {
+                let status = StatusCode::TOO_MANY_REQUESTS;
+                let headers = [(RETRY_AFTER, retry_after.to_string())];
+                return (status, headers, TOO_MANY_REQUESTS_ERR_MSG).into_response();
+            }
+            AppError::InfraError(_) => (StatusCode::INTERNAL_SERVER_ERROR, DB_ERR_MSG.to_string()),
         };
         res.into_response()
     }
@@ -1,3 +1,9 @@
 pub mod database;
+pub mod filter;
+pub mod locale;
+pub mod moderation;
 pub mod name_generator;
+pub mod password;
+pub mod routing;
 pub mod shorten;
+pub mod token;
@@ -1,14 +1,23 @@
+use crate::auth::day_bounds;
 use crate::config::DBConfig;
 
 use super::name_generator::GeneratedName;
 use crate::errors::AppError;
 use crate::schema::links::dsl::*;
+use diesel::connection::{AnsiTransactionManager, TransactionManager};
+use diesel::pg::Pg;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::sql_types::{Bool, Text};
+use serde::{Deserialize, Serialize};
 use url::Url;
+use utoipa::ToSchema;
 
-use crate::schema::{self, links};
+use crate::schema::{
+    self, api_keys, audit_log, banned_domains, link_reports, link_rules, link_variants, links,
+};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use std::collections::{BTreeMap, HashSet};
 use std::time::{Duration, SystemTime};
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
@@ -20,6 +29,126 @@ pub struct Link {
     pub original_link: String,
     pub short_link: String,
     pub created_at: SystemTime,
+    pub management_token_hash: Option<String>,
+    pub expires_at: SystemTime,
+    pub redirect_type: String,
+    pub api_key_id: Option<i32>,
+    pub visit_count: i64,
+    /// Set when the owner deleted the link, the row is kept as a tombstone for a while.
+    pub deleted_at: Option<SystemTime>,
+    /// Set while a moderator keeps the link from being followed.
+    pub disabled_at: Option<SystemTime>,
+    /// Visitors see a page with the destination before they're sent there.
+    pub interstitial: bool,
+    /// Argon2 hash of the password visitors have to enter before they're sent on.
+    pub password_hash: Option<String>,
+    /// How often the link may still be followed, `None` means there's no limit.
+    pub remaining_visits: Option<i32>,
+    /// The link can't be followed before this moment.
+    pub not_before: Option<SystemTime>,
+    /// Returning visitors are sent to the variant they got the first time.
+    pub sticky_variants: bool,
+    /// The query string of a visit is added to the destination.
+    pub forward_query: bool,
+    /// A path after the name is appended to the path of the destination.
+    pub forward_path: bool,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = schema::api_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    /// Admin keys may use the moderation API.
+    pub is_admin: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::api_keys)]
+pub struct NewApiKey<'a> {
+    pub name: &'a str,
+    pub key_hash: &'a str,
+    pub daily_quota: Option<i32>,
+    pub is_admin: bool,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = schema::banned_domains)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct BannedDomain {
+    pub domain: String,
+    pub reason: Option<String>,
+    pub created_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::banned_domains)]
+pub struct NewBannedDomain<'a> {
+    pub domain: &'a str,
+    pub reason: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = schema::audit_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditEntry {
+    pub id: i32,
+    pub api_key_id: Option<i32>,
+    pub action: String,
+    pub target: String,
+    pub details: Option<String>,
+    pub created_at: SystemTime,
+}
+
+/// Every moderation action is stored together with one of these, in the same transaction.
+#[derive(Insertable)]
+#[diesel(table_name = schema::audit_log)]
+pub struct NewAuditEntry<'a> {
+    /// The admin key that took the action, `None` for actions taken automatically.
+    pub api_key_id: Option<i32>,
+    pub action: &'a str,
+    pub target: &'a str,
+    pub details: Option<String>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = schema::link_reports)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkReport {
+    pub id: i32,
+    pub reason: String,
+    pub details: Option<String>,
+    pub reporter_agent: Option<String>,
+    pub api_key_id: Option<i32>,
+    pub created_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::link_reports)]
+pub struct NewLinkReport<'a> {
+    pub reason: &'a str,
+    pub details: Option<&'a str>,
+    /// Identifies the reporter without storing their IP address.
+    pub reporter_hash: &'a str,
+    pub reporter_agent: Option<&'a str>,
+    pub api_key_id: Option<i32>,
+}
+
+/// Narrows down a link search, filters left at `None` match every link.
+#[derive(Default)]
+pub struct LinkFilter {
+    /// Matched case-insensitively anywhere in the destination or that of a rule or variant.
+    pub destination: Option<String>,
+    /// A regular expression the destination or that of a rule or variant must match, see
+    /// `domain_pattern`.
+    pub domain_pattern: Option<String>,
+    /// Matched anywhere in the canonical name.
+    pub name: Option<String>,
+    pub created_after: Option<SystemTime>,
+    pub created_before: Option<SystemTime>,
+    pub offset: i64,
+    pub limit: i64,
 }
 
 #[derive(Insertable)]
@@ -27,48 +156,804 @@ pub struct Link {
 pub struct NewLink<'a> {
     pub original_link: &'a str,
     pub short_link: &'a str,
+    pub management_token_hash: &'a str,
+    pub expires_at: SystemTime,
+    pub redirect_type: &'a str,
+    pub api_key_id: Option<i32>,
+    pub interstitial: bool,
+    pub password_hash: Option<&'a str>,
+    pub remaining_visits: Option<i32>,
+    pub not_before: Option<SystemTime>,
+    pub sticky_variants: bool,
+    pub forward_query: bool,
+    pub forward_path: bool,
+}
+
+/// The columns an owner can change after creating a link. Fields left at `None` are kept.
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = links)]
+pub struct LinkChanges {
+    pub original_link: Option<String>,
+    pub expires_at: Option<SystemTime>,
+    pub redirect_type: Option<String>,
 }
 
+/// Sends visitors that match all of its conditions to its own destination. Conditions left at
+/// `None` match every visitor.
+#[derive(Queryable, Selectable, Insertable, Clone, Debug, PartialEq)]
+#[diesel(table_name = schema::link_rules)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkRule {
+    pub platform: Option<String>,
+    /// A primary language subtag like `de`, matched against the most preferred language.
+    pub language: Option<String>,
+    /// An ISO 3166-1 alpha-2 country code, looked up in the GeoIP database.
+    pub country: Option<String>,
+    /// Minutes since midnight UTC.
+    pub starts_at_minute: Option<i32>,
+    pub ends_at_minute: Option<i32>,
+    pub destination: String,
+}
+
+/// One of the destinations a link splits its visitors between.
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = schema::link_variants)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LinkVariant {
+    /// Identifies the variant within its link, this is what the sticky cookie holds.
+    pub position: i32,
+    pub destination: String,
+    /// Visitors get a variant in proportion to its weight.
+    pub weight: i32,
+    pub visit_count: i64,
+}
+
+#[derive(Insertable, Clone, Debug, PartialEq)]
+#[diesel(table_name = schema::link_variants)]
+pub struct NewVariant {
+    pub destination: String,
+    pub weight: i32,
+}
+
+/// A link that's ready to be stored, used to store many links at once.
+pub struct PendingLink {
+    pub original: Url,
+    pub name: GeneratedName,
+    pub settings: LinkSettings,
+}
+
+/// Everything that's stored next to a new link besides its name and destination.
+pub struct LinkSettings {
+    pub management_token_hash: String,
+    pub expires_at: SystemTime,
+    pub redirect_type: RedirectType,
+    /// The API key the link was created with, if any.
+    pub api_key_id: Option<i32>,
+    pub interstitial: bool,
+    pub password_hash: Option<String>,
+    /// How often the link may be followed, `None` means there's no limit.
+    pub remaining_visits: Option<i32>,
+    /// When the link starts working, right away when it's `None`.
+    pub not_before: Option<SystemTime>,
+    /// Routing rules in the order they're evaluated.
+    pub rules: Vec<LinkRule>,
+    /// Destinations for the visitors that match no rule, empty when they go to the link itself.
+    pub variants: Vec<NewVariant>,
+    pub sticky_variants: bool,
+    pub forward_query: bool,
+    pub forward_path: bool,
+}
+
+/// Whether browsers and search engines may remember where a link leads. Links are temporary
+/// unless asked otherwise, so they can still be edited or deleted after they were shared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectType {
+    Permanent,
+    #[default]
+    Temporary,
+}
+
+impl RedirectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectType::Permanent => "permanent",
+            RedirectType::Temporary => "temporary",
+        }
+    }
+
+    /// Unknown values can only come from manual edits, they fall back to the default.
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "permanent" => RedirectType::Permanent,
+            _ => RedirectType::Temporary,
+        }
+    }
+}
+
+sql_function!(fn levenshtein(source: Text, target: Text) -> Integer);
+/// Suggestions have to start with this many of the same characters as the missing name.
+const SUGGESTION_PREFIX_LENGTH: usize = 3;
+diesel::infix_operator!(MatchesRegex, " ~* ", backend: Pg);
+
 pub trait NamesRepository {
-    fn store_name(&mut self, original: &Url, generated: &GeneratedName) -> Result<(), AppError>;
+    fn store_name(
+        &mut self,
+        original: &Url,
+        generated: &GeneratedName,
+        settings: &LinkSettings,
+    ) -> Result<(), AppError>;
+    /// Stores all links in a single transaction, either all of them are stored or none are.
+    fn store_names(&mut self, pending: &[PendingLink]) -> Result<(), AppError>;
     fn name_exists(&mut self, name: &GeneratedName) -> Result<bool, AppError>;
-    fn retrieve_original_name(&mut self, name: &GeneratedName) -> Result<String, AppError>;
+    /// Checks many names in one query, returning the canonical form of those that are taken.
+    fn existing_names(&mut self, names: &[&GeneratedName]) -> Result<HashSet<String>, AppError>;
+    /// Returns which of the given domains are banned.
+    fn banned_among(&mut self, domains: &[String]) -> Result<HashSet<String>, AppError>;
+    /// Looks up a link that can be followed, i.e. one that is active and hasn't expired, been
+    /// deleted, disabled or used up.
+    fn retrieve_active_link(&mut self, name: &GeneratedName) -> Result<Link, AppError>;
+    /// Follows a link that can still be followed. The visit is counted and one of the remaining
+    /// visits is used up in the same query, so concurrent visitors can't exceed the limit.
+    fn retrieve_original_name(&mut self, name: &GeneratedName) -> Result<Link, AppError>;
+    /// Looks up a link regardless of whether it has expired or been deleted.
+    fn retrieve_link(&mut self, name: &GeneratedName) -> Result<Link, AppError>;
+    /// Returns the routing rules of a link in the order they're evaluated.
+    fn routing_rules(&mut self, link: &Link) -> Result<Vec<LinkRule>, AppError>;
+    /// The variants of a link in the order they were given.
+    fn variants(&mut self, link: &Link) -> Result<Vec<LinkVariant>, AppError>;
+    /// Counts a visit that was sent to the variant at `position`.
+    fn record_variant_visit(&mut self, link: &Link, position: i32) -> Result<(), AppError>;
+    fn update_link(&mut self, name: &GeneratedName, changes: &LinkChanges) -> Result<(), AppError>;
+    /// Turns the link into a tombstone, which keeps its name reserved until it's cleaned up.
+    fn delete_link(&mut self, name: &GeneratedName) -> Result<(), AppError>;
+    /// Finds the name of a link that can be followed with the smallest edit distance to `name`,
+    /// as long as that distance doesn't exceed `max_distance`.
+    fn closest_name(
+        &mut self,
+        name: &GeneratedName,
+        max_distance: i32,
+    ) -> Result<Option<String>, AppError>;
+}
+
+pub trait ApiKeysRepository {
+    fn create_api_key(&mut self, new_key: &NewApiKey) -> Result<ApiKey, AppError>;
+    /// Looks up a key that hasn't been revoked by the hash of its secret.
+    fn find_api_key(&mut self, hash: &str) -> Result<Option<ApiKey>, AppError>;
+    fn links_for_key(&mut self, api_key: &ApiKey) -> Result<Vec<Link>, AppError>;
+}
+
+pub trait ModerationRepository {
+    /// Returns one page of the links matching the filter, newest first, and the number of
+    /// matching links on all pages.
+    fn search_links(&mut self, filter: &LinkFilter) -> Result<(Vec<Link>, i64), AppError>;
+    fn set_link_disabled(
+        &mut self,
+        name: &GeneratedName,
+        disabled: bool,
+        audit: &NewAuditEntry,
+    ) -> Result<Link, AppError>;
+    /// Turns the link into a disabled tombstone. Its name stays reserved until the tombstone is
+    /// cleaned up, so a link that was taken down can't be registered again right away.
+    fn take_down_link(
+        &mut self,
+        name: &GeneratedName,
+        audit: &NewAuditEntry,
+    ) -> Result<(), AppError>;
+    /// Bans a domain and disables the links that lead to it, returning how many were disabled.
+    fn ban_domain(
+        &mut self,
+        ban: &NewBannedDomain,
+        pattern: &str,
+        audit: &NewAuditEntry,
+    ) -> Result<(BannedDomain, usize), AppError>;
+    fn unban_domain(&mut self, domain: &str, audit: &NewAuditEntry) -> Result<(), AppError>;
+    fn banned_domains(&mut self) -> Result<Vec<BannedDomain>, AppError>;
+    /// Stores a report about a link that hasn't been deleted. Once `disable_threshold`
+    /// different reporters have open reports about the link it's disabled, in which case
+    /// `true` is returned. Repeated reports from the same reporter are ignored.
+    fn add_report(
+        &mut self,
+        name: &GeneratedName,
+        report: &NewLinkReport,
+        disable_threshold: Option<u32>,
+    ) -> Result<bool, AppError>;
+    /// Returns one page of unresolved reports with the links they're about, newest first.
+    fn open_reports(
+        &mut self,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<(LinkReport, Link)>, i64), AppError>;
+    /// Resolves the open reports about a link, returning how many there were.
+    fn resolve_reports(
+        &mut self,
+        name: &GeneratedName,
+        audit: &NewAuditEntry,
+    ) -> Result<usize, AppError>;
+    fn audit_log(&mut self, offset: i64, limit: i64) -> Result<(Vec<AuditEntry>, i64), AppError>;
 }
 
 pub struct PostgresRepository(PgConnection);
 
+/// What `PostgresRepository::tentatively` returns, and whether the changes made for it are kept.
+pub enum Tentative<T> {
+    Keep(T),
+    Discard(T),
+}
+
 impl NamesRepository for PostgresRepository {
+    fn existing_names(&mut self, names: &[&GeneratedName]) -> Result<HashSet<String>, AppError> {
+        let canonical_names: Vec<String> = names.iter().map(|name| name.canonical()).collect();
+        let taken: Vec<String> = links
+            .select(short_link)
+            .filter(short_link.eq_any(&canonical_names))
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        Ok(taken.into_iter().collect())
+    }
+
+    fn banned_among(&mut self, domains: &[String]) -> Result<HashSet<String>, AppError> {
+        let banned: Vec<String> = banned_domains::table
+            .select(banned_domains::domain)
+            .filter(banned_domains::domain.eq_any(domains))
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        Ok(banned.into_iter().collect())
+    }
+
+    fn store_names(&mut self, pending: &[PendingLink]) -> Result<(), AppError> {
+        let canonical_names: Vec<String> = pending.iter().map(|p| p.name.canonical()).collect();
+        let new_links: Vec<NewLink> = pending
+            .iter()
+            .zip(&canonical_names)
+            .map(|(p, canonical_name)| NewLink {
+                original_link: p.original.as_str(),
+                short_link: canonical_name,
+                management_token_hash: &p.settings.management_token_hash,
+                expires_at: p.settings.expires_at,
+                redirect_type: p.settings.redirect_type.as_str(),
+                api_key_id: p.settings.api_key_id,
+                interstitial: p.settings.interstitial,
+                password_hash: p.settings.password_hash.as_deref(),
+                remaining_visits: p.settings.remaining_visits,
+                not_before: p.settings.not_before,
+                sticky_variants: p.settings.sticky_variants,
+                forward_query: p.settings.forward_query,
+                forward_path: p.settings.forward_path,
+            })
+            .collect();
+        let mut per_key: BTreeMap<i32, usize> = BTreeMap::new();
+        for key_id in pending.iter().filter_map(|p| p.settings.api_key_id) {
+            *per_key.entry(key_id).or_default() += 1;
+        }
+        self.0.transaction::<_, AppError, _>(|conn| {
+            for (&key_id, &count) in &per_key {
+                enforce_quota(conn, key_id, count)?;
+            }
+            // Postgres returns the ids in the order the rows were given.
+            let ids: Vec<i32> = diesel::insert_into(links)
+                .values(&new_links)
+                .returning(id)
+                .get_results(conn)?;
+            for (link_id, p) in ids.into_iter().zip(pending) {
+                insert_rules(conn, link_id, &p.settings.rules)?;
+                insert_variants(conn, link_id, &p.settings.variants)?;
+            }
+            Ok(())
+        })
+    }
+
     fn name_exists(&mut self, name: &GeneratedName) -> Result<bool, AppError> {
         let result: Option<Link> = links
-            .filter(short_link.eq(&name.0))
+            .filter(short_link.eq(name.canonical()))
             .first::<Link>(&mut self.0)
             .optional()
             .map_err(AppError::DatabaseError)?;
         Ok(result.is_some())
     }
 
-    fn retrieve_original_name(&mut self, name: &GeneratedName) -> Result<String, AppError> {
+    fn retrieve_active_link(&mut self, name: &GeneratedName) -> Result<Link, AppError> {
+        let now = SystemTime::now();
+        let result: Option<Link> = links
+            .filter(short_link.eq(name.canonical()))
+            .filter(not_before.is_null().or(not_before.le(now)))
+            .filter(expires_at.gt(now))
+            .filter(deleted_at.is_null())
+            .filter(disabled_at.is_null())
+            .filter(remaining_visits.is_null().or(remaining_visits.gt(0)))
+            .first::<Link>(&mut self.0)
+            .optional()
+            .map_err(AppError::DatabaseError)?;
+        result.ok_or(AppError::NotFoundError)
+    }
+
+    fn retrieve_original_name(&mut self, name: &GeneratedName) -> Result<Link, AppError> {
+        // Unlimited links keep `NULL`, as `NULL - 1` is `NULL` again.
+        let now = SystemTime::now();
+        let result: Option<Link> = diesel::update(links)
+            .filter(short_link.eq(name.canonical()))
+            .filter(not_before.is_null().or(not_before.le(now)))
+            .filter(expires_at.gt(now))
+            .filter(deleted_at.is_null())
+            .filter(disabled_at.is_null())
+            .filter(remaining_visits.is_null().or(remaining_visits.gt(0)))
+            .set((
+                visit_count.eq(visit_count + 1),
+                remaining_visits.eq(remaining_visits - 1),
+            ))
+            .returning(Link::as_returning())
+            .get_result(&mut self.0)
+            .optional()
+            .map_err(AppError::DatabaseError)?;
+        result.ok_or(AppError::NotFoundError)
+    }
+
+    fn retrieve_link(&mut self, name: &GeneratedName) -> Result<Link, AppError> {
         let result: Option<Link> = links
-            .filter(short_link.eq(&name.0))
+            .filter(short_link.eq(name.canonical()))
             .first::<Link>(&mut self.0)
             .optional()
             .map_err(AppError::DatabaseError)?;
-        result
-            .ok_or(AppError::NotFoundError)
-            .map(|link| link.original_link)
+        result.ok_or(AppError::NotFoundError)
+    }
+
+    fn update_link(&mut self, name: &GeneratedName, changes: &LinkChanges) -> Result<(), AppError> {
+        let updated = diesel::update(links)
+            .filter(short_link.eq(name.canonical()))
+            .filter(deleted_at.is_null())
+            .set(changes)
+            .execute(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        match updated {
+            0 => Err(AppError::NotFoundError),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_link(&mut self, name: &GeneratedName) -> Result<(), AppError> {
+        let deleted = diesel::update(links)
+            .filter(short_link.eq(name.canonical()))
+            .filter(deleted_at.is_null())
+            .set(deleted_at.eq(SystemTime::now()))
+            .execute(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        match deleted {
+            0 => Err(AppError::NotFoundError),
+            _ => Ok(()),
+        }
     }
 
-    fn store_name(&mut self, original: &Url, generated: &GeneratedName) -> Result<(), AppError> {
+    fn store_name(
+        &mut self,
+        original: &Url,
+        generated: &GeneratedName,
+        settings: &LinkSettings,
+    ) -> Result<(), AppError> {
+        let canonical_name = generated.canonical();
         let new_link = NewLink {
             original_link: original.as_str(),
-            short_link: &generated.0,
+            short_link: &canonical_name,
+            management_token_hash: &settings.management_token_hash,
+            expires_at: settings.expires_at,
+            redirect_type: settings.redirect_type.as_str(),
+            api_key_id: settings.api_key_id,
+            interstitial: settings.interstitial,
+            password_hash: settings.password_hash.as_deref(),
+            remaining_visits: settings.remaining_visits,
+            not_before: settings.not_before,
+            sticky_variants: settings.sticky_variants,
+            forward_query: settings.forward_query,
+            forward_path: settings.forward_path,
         };
-        diesel::insert_into(links)
-            .values(new_link)
+        self.0.transaction::<_, AppError, _>(|conn| {
+            if let Some(key_id) = settings.api_key_id {
+                enforce_quota(conn, key_id, 1)?;
+            }
+            let link_id: i32 = diesel::insert_into(links)
+                .values(new_link)
+                .returning(id)
+                .get_result(conn)?;
+            insert_rules(conn, link_id, &settings.rules)?;
+            Ok(insert_variants(conn, link_id, &settings.variants)?)
+        })
+    }
+
+    fn routing_rules(&mut self, link: &Link) -> Result<Vec<LinkRule>, AppError> {
+        link_rules::table
+            .filter(link_rules::link_id.eq(link.id))
+            .order(link_rules::position)
+            .select(LinkRule::as_select())
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)
+    }
+
+    fn variants(&mut self, link: &Link) -> Result<Vec<LinkVariant>, AppError> {
+        link_variants::table
+            .filter(link_variants::link_id.eq(link.id))
+            .order(link_variants::position)
+            .select(LinkVariant::as_select())
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)
+    }
+
+    fn record_variant_visit(&mut self, link: &Link, position: i32) -> Result<(), AppError> {
+        diesel::update(link_variants::table)
+            .filter(link_variants::link_id.eq(link.id))
+            .filter(link_variants::position.eq(position))
+            .set(link_variants::visit_count.eq(link_variants::visit_count + 1))
             .execute(&mut self.0)
             .map_err(AppError::DatabaseError)?;
         Ok(())
     }
+
+    fn closest_name(
+        &mut self,
+        name: &GeneratedName,
+        max_distance: i32,
+    ) -> Result<Option<String>, AppError> {
+        // Only names starting the same way are compared, so the index on the names narrows
+        // the candidates down instead of computing the distance to every link.
+        let canonical_name = name.canonical();
+        let prefix: String = canonical_name
+            .chars()
+            .take(SUGGESTION_PREFIX_LENGTH)
+            .collect();
+        let now = SystemTime::now();
+        links
+            .select(short_link)
+            .filter(short_link.like(format!("{}%", escape_like(&prefix))))
+            .filter(not_before.is_null().or(not_before.le(now)))
+            .filter(expires_at.gt(now))
+            .filter(deleted_at.is_null())
+            .filter(disabled_at.is_null())
+            .filter(remaining_visits.is_null().or(remaining_visits.gt(0)))
+            .filter(levenshtein(short_link, &canonical_name).le(max_distance))
+            .order(levenshtein(short_link, &canonical_name))
+            .first::<String>(&mut self.0)
+            .optional()
+            .map_err(AppError::DatabaseError)
+    }
+}
+
+impl ApiKeysRepository for PostgresRepository {
+    fn create_api_key(&mut self, new_key: &NewApiKey) -> Result<ApiKey, AppError> {
+        diesel::insert_into(api_keys::table)
+            .values(new_key)
+            .returning(ApiKey::as_returning())
+            .get_result(&mut self.0)
+            .map_err(AppError::DatabaseError)
+    }
+
+    fn find_api_key(&mut self, hash: &str) -> Result<Option<ApiKey>, AppError> {
+        api_keys::table
+            .filter(api_keys::key_hash.eq(hash))
+            .filter(api_keys::revoked_at.is_null())
+            .select(ApiKey::as_select())
+            .first(&mut self.0)
+            .optional()
+            .map_err(AppError::DatabaseError)
+    }
+
+    fn links_for_key(&mut self, api_key: &ApiKey) -> Result<Vec<Link>, AppError> {
+        links
+            .filter(api_key_id.eq(api_key.id))
+            .filter(deleted_at.is_null())
+            .order(created_at.desc())
+            .select(Link::as_select())
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)
+    }
+}
+
+impl ModerationRepository for PostgresRepository {
+    fn search_links(&mut self, filter: &LinkFilter) -> Result<(Vec<Link>, i64), AppError> {
+        let total = filtered_links(filter)
+            .count()
+            .get_result(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        let page = filtered_links(filter)
+            .order((created_at.desc(), id.desc()))
+            .offset(filter.offset)
+            .limit(filter.limit)
+            .select(Link::as_select())
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        Ok((page, total))
+    }
+
+    fn set_link_disabled(
+        &mut self,
+        name: &GeneratedName,
+        disabled: bool,
+        audit: &NewAuditEntry,
+    ) -> Result<Link, AppError> {
+        let now = disabled.then(SystemTime::now);
+        self.0
+            .transaction(|conn| {
+                let link: Link = diesel::update(links)
+                    .filter(short_link.eq(name.canonical()))
+                    .set(disabled_at.eq(now))
+                    .returning(Link::as_returning())
+                    .get_result(conn)?;
+                // Enabling a link dismisses its reports, otherwise the next one would disable it
+                // right away again.
+                if !disabled {
+                    resolve_open_reports(conn, link.id)?;
+                }
+                record_audit(conn, audit)?;
+                Ok(link)
+            })
+            .map_err(not_found_or_database_error)
+    }
+
+    fn take_down_link(
+        &mut self,
+        name: &GeneratedName,
+        audit: &NewAuditEntry,
+    ) -> Result<(), AppError> {
+        let now = SystemTime::now();
+        self.0
+            .transaction(|conn| {
+                let link: Link = links
+                    .filter(short_link.eq(name.canonical()))
+                    .for_update()
+                    .first(conn)?;
+                // Links the owner deleted before keep their time of deletion, so they're cleaned
+                // up when they would have been anyway.
+                diesel::update(links.find(link.id))
+                    .set((
+                        deleted_at.eq(link.deleted_at.unwrap_or(now)),
+                        disabled_at.eq(link.disabled_at.unwrap_or(now)),
+                    ))
+                    .execute(conn)?;
+                record_audit(conn, audit)
+            })
+            .map_err(not_found_or_database_error)
+    }
+
+    fn ban_domain(
+        &mut self,
+        ban: &NewBannedDomain,
+        pattern: &str,
+        audit: &NewAuditEntry,
+    ) -> Result<(BannedDomain, usize), AppError> {
+        self.0
+            .transaction(|conn| {
+                let banned = diesel::insert_into(banned_domains::table)
+                    .values(ban)
+                    .on_conflict(banned_domains::domain)
+                    .do_update()
+                    .set(banned_domains::reason.eq(ban.reason))
+                    .returning(BannedDomain::as_returning())
+                    .get_result(conn)?;
+                let disabled = diesel::update(links)
+                    .filter(disabled_at.is_null())
+                    .filter(deleted_at.is_null())
+                    .filter(matches_pattern(pattern))
+                    .set(disabled_at.eq(SystemTime::now()))
+                    .execute(conn)?;
+                record_audit(conn, audit)?;
+                Ok((banned, disabled))
+            })
+            .map_err(AppError::DatabaseError)
+    }
+
+    fn unban_domain(&mut self, domain: &str, audit: &NewAuditEntry) -> Result<(), AppError> {
+        self.0
+            .transaction(|conn| {
+                let deleted = diesel::delete(banned_domains::table)
+                    .filter(banned_domains::domain.eq(domain))
+                    .execute(conn)?;
+                if deleted == 0 {
+                    return Err(diesel::result::Error::NotFound);
+                }
+                record_audit(conn, audit)
+            })
+            .map_err(not_found_or_database_error)
+    }
+
+    fn banned_domains(&mut self) -> Result<Vec<BannedDomain>, AppError> {
+        banned_domains::table
+            .order(banned_domains::domain)
+            .select(BannedDomain::as_select())
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)
+    }
+
+    fn add_report(
+        &mut self,
+        name: &GeneratedName,
+        report: &NewLinkReport,
+        disable_threshold: Option<u32>,
+    ) -> Result<bool, AppError> {
+        self.0
+            .transaction(|conn| {
+                let link: Link = links
+                    .filter(short_link.eq(name.canonical()))
+                    .filter(deleted_at.is_null())
+                    .select(Link::as_select())
+                    .first(conn)?;
+                diesel::insert_into(link_reports::table)
+                    .values((link_reports::link_id.eq(link.id), report))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                let Some(threshold) = disable_threshold else {
+                    return Ok(false);
+                };
+                if link.disabled_at.is_some() {
+                    return Ok(false);
+                }
+                let reporters: i64 = link_reports::table
+                    .filter(link_reports::link_id.eq(link.id))
+                    .filter(link_reports::resolved_at.is_null())
+                    .count()
+                    .get_result(conn)?;
+                if reporters < i64::from(threshold) {
+                    return Ok(false);
+                }
+                diesel::update(links)
+                    .filter(id.eq(link.id))
+                    .set(disabled_at.eq(SystemTime::now()))
+                    .execute(conn)?;
+                let audit = NewAuditEntry {
+                    api_key_id: None,
+                    action: "disable_link",
+                    target: &link.short_link,
+                    details: Some(format!("Reported by {reporters} clients.")),
+                };
+                record_audit(conn, &audit)?;
+                Ok(true)
+            })
+            .map_err(not_found_or_database_error)
+    }
+
+    fn open_reports(
+        &mut self,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<(LinkReport, Link)>, i64), AppError> {
+        let total = link_reports::table
+            .filter(link_reports::resolved_at.is_null())
+            .count()
+            .get_result(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        let page = link_reports::table
+            .inner_join(links)
+            .filter(link_reports::resolved_at.is_null())
+            .order(link_reports::id.desc())
+            .offset(offset)
+            .limit(limit)
+            .select((LinkReport::as_select(), Link::as_select()))
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        Ok((page, total))
+    }
+
+    fn resolve_reports(
+        &mut self,
+        name: &GeneratedName,
+        audit: &NewAuditEntry,
+    ) -> Result<usize, AppError> {
+        self.0
+            .transaction(|conn| {
+                let link_id: i32 = links
+                    .filter(short_link.eq(name.canonical()))
+                    .select(id)
+                    .first(conn)?;
+                let resolved = resolve_open_reports(conn, link_id)?;
+                record_audit(conn, audit)?;
+                Ok(resolved)
+            })
+            .map_err(not_found_or_database_error)
+    }
+
+    fn audit_log(&mut self, offset: i64, limit: i64) -> Result<(Vec<AuditEntry>, i64), AppError> {
+        let total = audit_log::table
+            .count()
+            .get_result(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        let page = audit_log::table
+            .order(audit_log::id.desc())
+            .offset(offset)
+            .limit(limit)
+            .select(AuditEntry::as_select())
+            .load(&mut self.0)
+            .map_err(AppError::DatabaseError)?;
+        Ok((page, total))
+    }
+}
+
+/// Builds the query for a link search, once for counting and once for the page itself.
+fn filtered_links(filter: &LinkFilter) -> links::BoxedQuery<'_, Pg> {
+    let mut query = links.into_boxed();
+    if let Some(destination) = &filter.destination {
+        query = query.filter(destination_contains(destination));
+    }
+    if let Some(pattern) = &filter.domain_pattern {
+        query = query.filter(matches_pattern(pattern));
+    }
+    if let Some(name) = &filter.name {
+        query = query.filter(short_link.like(format!("%{}%", escape_like(name))));
+    }
+    if let Some(after) = filter.created_after {
+        query = query.filter(created_at.ge(after));
+    }
+    if let Some(before) = filter.created_before {
+        query = query.filter(created_at.lt(before));
+    }
+    query
+}
+
+/// A condition on links that also holds when it holds for one of their rules or variants.
+type LinkCondition = Box<dyn BoxableExpression<links::table, Pg, SqlType = Bool>>;
+
+/// Matches the destinations case-insensitively against a Postgres regular expression.
+fn matches_pattern(pattern: &str) -> LinkCondition {
+    let regex = || pattern.to_string().into_sql::<Text>();
+    let rule_links = link_rules::table
+        .filter(MatchesRegex::new(link_rules::destination, regex()))
+        .select(link_rules::link_id);
+    let variant_links = link_variants::table
+        .filter(MatchesRegex::new(link_variants::destination, regex()))
+        .select(link_variants::link_id);
+    Box::new(
+        MatchesRegex::new(original_link, regex())
+            .or(id.eq_any(rule_links))
+            .or(id.eq_any(variant_links)),
+    )
+}
+
+/// Finds the text anywhere in the destinations, ignoring case.
+fn destination_contains(text: &str) -> LinkCondition {
+    let pattern = format!("%{}%", escape_like(text));
+    let rule_links = link_rules::table
+        .filter(link_rules::destination.ilike(pattern.clone()))
+        .select(link_rules::link_id);
+    let variant_links = link_variants::table
+        .filter(link_variants::destination.ilike(pattern.clone()))
+        .select(link_variants::link_id);
+    Box::new(
+        original_link
+            .ilike(pattern)
+            .or(id.eq_any(rule_links))
+            .or(id.eq_any(variant_links)),
+    )
+}
+
+/// Keeps `%` and `_` in user input from acting as wildcards in a `LIKE` pattern.
+fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn record_audit(
+    conn: &mut PgConnection,
+    audit: &NewAuditEntry,
+) -> Result<(), diesel::result::Error> {
+    diesel::insert_into(audit_log::table)
+        .values(audit)
+        .execute(conn)?;
+    Ok(())
+}
+
+fn resolve_open_reports(
+    conn: &mut PgConnection,
+    link: i32,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(link_reports::table)
+        .filter(link_reports::link_id.eq(link))
+        .filter(link_reports::resolved_at.is_null())
+        .set(link_reports::resolved_at.eq(SystemTime::now()))
+        .execute(conn)
+}
+
+fn not_found_or_database_error(e: diesel::result::Error) -> AppError {
+    match e {
+        diesel::result::Error::NotFound => AppError::NotFoundError,
+        e => AppError::DatabaseError(e),
+    }
 }
 
 impl PostgresRepository {
@@ -81,15 +966,116 @@ impl PostgresRepository {
         Ok(Self::from_connection(connection))
     }
 
-    pub fn cleanup_old_links(&mut self) -> Result<usize, diesel::result::Error> {
-        let week = Duration::from_secs_f64(604800.0);
-        let last_week = SystemTime::now() + week;
+    /// Runs `work` in a transaction that is only committed when it returns `Keep`. Errors roll
+    /// the transaction back as well.
+    pub fn tentatively<T>(
+        &mut self,
+        work: impl FnOnce(&mut Self) -> Result<Tentative<T>, AppError>,
+    ) -> Result<T, AppError> {
+        AnsiTransactionManager::begin_transaction(&mut self.0)?;
+        let result = work(self);
+        let (value, keep) = match result {
+            Ok(Tentative::Keep(value)) => (value, true),
+            Ok(Tentative::Discard(value)) => (value, false),
+            Err(e) => {
+                if let Err(rollback_error) =
+                    AnsiTransactionManager::rollback_transaction(&mut self.0)
+                {
+                    log::error!("A transaction couldn't be rolled back: {rollback_error}");
+                }
+                return Err(e);
+            }
+        };
+        match keep {
+            true => AnsiTransactionManager::commit_transaction(&mut self.0)?,
+            false => AnsiTransactionManager::rollback_transaction(&mut self.0)?,
+        }
+        Ok(value)
+    }
+
+    /// Removes the tombstones of links that expired or were deleted more than `grace` ago,
+    /// which frees up their names.
+    pub fn cleanup_old_links(&mut self, grace: Duration) -> Result<usize, diesel::result::Error> {
+        let cutoff = SystemTime::now() - grace;
         diesel::delete(links)
-            .filter(created_at.lt(last_week))
+            .filter(
+                deleted_at
+                    .lt(cutoff)
+                    .or(deleted_at.is_null().and(expires_at.lt(cutoff))),
+            )
             .execute(&mut self.0)
     }
 }
 
+/// Fails when `count` more links would exceed today's quota of the key. The key's row stays
+/// locked until the transaction ends, so concurrent requests can't both take the last links.
+fn enforce_quota(conn: &mut PgConnection, key_id: i32, count: usize) -> Result<(), AppError> {
+    let quota: Option<i32> = api_keys::table
+        .find(key_id)
+        .select(api_keys::daily_quota)
+        .for_update()
+        .first(conn)?;
+    let Some(quota) = quota else {
+        return Ok(());
+    };
+    let (start_of_day, until_tomorrow) = day_bounds(SystemTime::now());
+    let used: i64 = links
+        .filter(api_key_id.eq(key_id))
+        .filter(created_at.ge(start_of_day))
+        .count()
+        .get_result(conn)?;
+    if used + count as i64 > i64::from(quota) {
+        return Err(AppError::TooManyRequestsError(until_tomorrow.as_secs()));
+    }
+    Ok(())
+}
+
+fn insert_rules(conn: &mut PgConnection, link_id: i32, rules: &[LinkRule]) -> QueryResult<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let new_rules: Vec<_> = rules
+        .iter()
+        .zip(0..)
+        .map(|(rule, position)| {
+            (
+                link_rules::link_id.eq(link_id),
+                link_rules::position.eq(position),
+                rule,
+            )
+        })
+        .collect();
+    diesel::insert_into(link_rules::table)
+        .values(new_rules)
+        .execute(conn)?;
+    Ok(())
+}
+
+fn insert_variants(
+    conn: &mut PgConnection,
+    link_id: i32,
+    variants: &[NewVariant],
+) -> QueryResult<()> {
+    if variants.is_empty() {
+        return Ok(());
+    }
+    let new_variants: Vec<_> = variants
+        .iter()
+        .zip(0..)
+        .map(|(variant, position)| {
+            (
+                link_variants::link_id.eq(link_id),
+                link_variants::position.eq(position),
+                variant,
+            )
+        })
+        .collect();
+    diesel::insert_into(link_variants::table)
+        .values(new_variants)
+        .execute(conn)?;
+    Ok(())
+}
+
 fn establish_connection(db_config: &DBConfig) -> Result<PgConnection, AppError> {
     let database_url = db_config.to_connection_string();
     PgConnection::establish(&database_url).map_err(|e| AppError::InfraError(e.to_string()))
@@ -101,3 +1087,54 @@ pub fn run_migration(db_config: &DBConfig) {
     conn.run_pending_migrations(MIGRATIONS).expect("foo");
     log::info!("Migrations ran successfully.");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::shorten::{ShortenOptions, ShortenService, Shortener};
+    use crate::NameGenerator;
+
+    /// Connects to the database of the test configuration. Nothing is committed, the test
+    /// transaction is rolled back when the connection is dropped.
+    fn test_repository() -> PostgresRepository {
+        let db_config = DBConfig::default();
+        run_migration(&db_config);
+        let mut repo = PostgresRepository::from_config(&db_config).unwrap();
+        repo.0.begin_test_transaction().unwrap();
+        repo
+    }
+
+    #[test]
+    #[ignore = "needs a PostgreSQL database"]
+    fn test_taken_down_names_stay_reserved_until_cleanup() {
+        let mut repo = test_repository();
+        let generator = NameGenerator::default();
+        let shortener = ShortenService::new("http://localhost:8080/", &generator);
+        let mut rng = rand::thread_rng();
+        let options = ShortenOptions {
+            alias: Some("modgone"),
+            ..Default::default()
+        };
+        let mut shorten = |link: &str, repo: &mut PostgresRepository| {
+            shortener.shorten_name(&mut link.to_string(), &options, repo, &mut rng)
+        };
+        shorten("https://phishing.example/", &mut repo).unwrap();
+        let audit = NewAuditEntry {
+            api_key_id: None,
+            action: "delete_link",
+            target: "modgone",
+            details: None,
+        };
+        let name = GeneratedName("modgone".to_string());
+        repo.take_down_link(&name, &audit).unwrap();
+
+        let tombstone = repo.retrieve_link(&name).unwrap();
+        assert!(tombstone.deleted_at.is_some());
+        assert!(tombstone.disabled_at.is_some());
+        let reused = shorten("https://evil.example/", &mut repo);
+        assert!(matches!(reused, Err(AppError::UserInputError(_))));
+
+        repo.cleanup_old_links(Duration::ZERO).unwrap();
+        assert!(shorten("https://example.com/", &mut repo).is_ok());
+    }
+}
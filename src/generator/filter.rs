@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::fs;
+
+/// Deny list consulted before a name is handed out. It is read from a plain text file
+/// where every line is either a single word, a `first+second` word pair or a `*text*`
+/// substring pattern. See `data/denylist.txt` for an example.
+#[derive(Debug, Default)]
+pub struct NameFilter {
+    words: HashSet<String>,
+    pairs: HashSet<(String, String)>,
+    patterns: Vec<String>,
+}
+
+impl NameFilter {
+    /// Reads the deny list at `path`. A missing file results in a filter that allows everything,
+    /// so deployments without a deny list keep working.
+    pub fn from_file(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(_) => {
+                log::warn!("No deny list was found at {path}. Generated names won't be filtered.");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut filter = Self::default();
+        let entries = contents
+            .lines()
+            .map(|line| line.trim().to_lowercase())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        for entry in entries {
+            if let Some(pattern) = entry.strip_prefix('*').and_then(|e| e.strip_suffix('*')) {
+                filter.patterns.push(pattern.to_string());
+            } else if let Some((first, second)) = entry.split_once('+') {
+                filter
+                    .pairs
+                    .insert(ordered_pair(first.trim(), second.trim()));
+            } else {
+                filter.words.insert(entry);
+            }
+        }
+        filter
+    }
+
+    /// Checks the two words a generated name is built from.
+    pub fn allows_words(&self, first: &str, second: &str) -> bool {
+        let first = first.to_lowercase();
+        let second = second.to_lowercase();
+        !self.words.contains(&first)
+            && !self.words.contains(&second)
+            && !self.pairs.contains(&ordered_pair(&first, &second))
+    }
+
+    /// Checks a complete name. The name is split on every character that isn't a letter so
+    /// the word and pair rules also apply to custom aliases such as `ugly-link`. Words are
+    /// checked once more split on case changes, which catches aliases such as `UglyLink`.
+    pub fn allows_name(&self, name: &str) -> bool {
+        let lowercase = name.to_lowercase();
+        if self.patterns.iter().any(|p| lowercase.contains(p.as_str())) {
+            return false;
+        }
+        let words: Vec<String> = lowercase
+            .split(|c: char| !c.is_alphabetic())
+            .filter(|w| !w.is_empty())
+            .map(str::to_string)
+            .collect();
+        self.allows_word_sequence(&words) && self.allows_word_sequence(&camel_case_words(name))
+    }
+
+    fn allows_word_sequence(&self, words: &[String]) -> bool {
+        let denied_word = words.iter().any(|w| self.words.contains(w));
+        let denied_pair = words
+            .windows(2)
+            .any(|pair| self.pairs.contains(&ordered_pair(&pair[0], &pair[1])));
+        !denied_word && !denied_pair
+    }
+}
+
+/// Splits a name into lowercase words, starting a new word at every character that isn't a
+/// letter and at every uppercase letter that follows a lowercase one.
+fn camel_case_words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut after_lowercase = false;
+    for c in name.chars() {
+        let starts_word = !c.is_alphabetic() || (c.is_uppercase() && after_lowercase);
+        if starts_word && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        if c.is_alphabetic() {
+            word.extend(c.to_lowercase());
+        }
+        after_lowercase = c.is_lowercase();
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn ordered_pair(first: &str, second: &str) -> (String, String) {
+    if first <= second {
+        (first.to_string(), second.to_string())
+    } else {
+        (second.to_string(), first.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DENY_LIST: &str = "# comment\nugly\nhuman+fat\n*bad*\n";
+
+    #[test]
+    fn test_denied_word_is_rejected() {
+        let filter = NameFilter::parse(DENY_LIST);
+        assert!(!filter.allows_words("Aardvark", "ugly"));
+        assert!(filter.allows_words("Aardvark", "able"));
+    }
+
+    #[test]
+    fn test_denied_pair_is_rejected_in_either_order() {
+        let filter = NameFilter::parse(DENY_LIST);
+        assert!(!filter.allows_words("Human", "fat"));
+        assert!(!filter.allows_words("fat", "Human"));
+        assert!(filter.allows_words("Human", "able"));
+    }
+
+    #[test]
+    fn test_pattern_is_matched_anywhere_in_name() {
+        let filter = NameFilter::parse(DENY_LIST);
+        assert!(!filter.allows_name("Aardvarkbadly123"));
+        assert!(filter.allows_name("Aardvarkable123"));
+    }
+
+    #[test]
+    fn test_alias_words_are_checked() {
+        let filter = NameFilter::parse(DENY_LIST);
+        assert!(!filter.allows_name("my-ugly-link"));
+        assert!(!filter.allows_name("fat_human"));
+        assert!(filter.allows_name("my-link"));
+    }
+
+    #[test]
+    fn test_camel_case_alias_words_are_checked() {
+        let filter = NameFilter::parse(DENY_LIST);
+        assert!(!filter.allows_name("MyUglyLink"));
+        assert!(!filter.allows_name("FatHuman"));
+        assert!(filter.allows_name("MyLink"));
+        assert_eq!(camel_case_words("HTMLPage2Go"), vec!["htmlpage", "go"]);
+    }
+}
@@ -0,0 +1,77 @@
+use deunicode::deunicode;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Parses an `Accept-Language` header into the primary language subtags it contains, most
+/// preferred first. `nl-BE,fr;q=0.8,en;q=0.5` becomes `["nl", "fr", "en"]`.
+pub fn preferred_languages(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.trim().split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let language = primary_language(tag);
+            if language.is_empty() || language == "*" || quality <= 0.0 {
+                return None;
+            }
+            Some((language, quality))
+        })
+        .collect();
+
+    // A stable sort keeps the header order for languages with the same quality.
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut result: Vec<String> = Vec::new();
+    for (language, _) in languages {
+        if !result.contains(&language) {
+            result.push(language);
+        }
+    }
+    result
+}
+
+/// The primary language subtag of a language tag, so `" fr-BE"` becomes `fr`.
+pub fn primary_language(tag: &str) -> String {
+    tag.trim()
+        .split('-')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// Turns a word from a locale pack into something that can safely be part of a URL.
+/// Accented letters are transliterated (`Éléphant` becomes `Elephant`) and everything
+/// that isn't an ASCII letter, digit or dash is dropped.
+pub fn transliterate(word: &str) -> String {
+    deunicode(word)
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferred_languages_are_sorted_by_quality() {
+        let result = preferred_languages("en;q=0.5, nl-BE, fr;q=0.8");
+        assert_eq!(result, vec!["nl", "fr", "en"]);
+    }
+
+    #[test]
+    fn test_preferred_languages_skip_wildcards_and_duplicates() {
+        let result = preferred_languages("nl-BE,nl;q=0.9,*;q=0.1");
+        assert_eq!(result, vec!["nl"]);
+    }
+
+    #[test]
+    fn test_transliterate_removes_accents() {
+        assert_eq!(transliterate("Éléphant"), "Elephant");
+        assert_eq!(transliterate("Pinguïn"), "Pinguin");
+        assert_eq!(transliterate("chauve-souris"), "chauve-souris");
+    }
+}
@@ -0,0 +1,612 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use url::Host;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::AppError;
+
+use super::{
+    database::{
+        AuditEntry, BannedDomain, Link, LinkFilter, LinkReport, ModerationRepository,
+        NewAuditEntry, NewBannedDomain, NewLinkReport,
+    },
+    name_generator::GeneratedName,
+    shorten::{format_timestamp, parse_timestamp},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const MAX_REPORT_DETAILS_LENGTH: usize = 1000;
+/// User agents are only kept as a hint for moderators, overly long ones are cut off.
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// Query parameters of the admin link search. All filters are optional and combined.
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkSearch {
+    /// Part of the destination, matched case-insensitively.
+    pub q: Option<String>,
+    /// Only links to this domain or one of its subdomains.
+    pub domain: Option<String>,
+    /// Part of the short name.
+    pub name: Option<String>,
+    /// Only links created at or after this RFC 3339 timestamp.
+    pub created_after: Option<String>,
+    /// Only links created before this RFC 3339 timestamp.
+    pub created_before: Option<String>,
+    /// The page to return, starting at 1.
+    pub page: Option<i64>,
+    /// The number of results per page, 50 by default and at most 200.
+    pub per_page: Option<i64>,
+}
+
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    /// The page to return, starting at 1.
+    pub page: Option<i64>,
+    /// The number of results per page, 50 by default and at most 200.
+    pub per_page: Option<i64>,
+}
+
+impl Pagination {
+    /// Returns the page number, page size and offset after validating them.
+    fn bounds(&self) -> Result<(i64, i64, i64), AppError> {
+        let page = self.page.unwrap_or(1);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+        if page < 1 {
+            let error_msg = "The page must be at least 1.".to_string();
+            return Err(AppError::UserInputError(error_msg));
+        }
+        if !(1..=MAX_PAGE_SIZE).contains(&per_page) {
+            let error_msg = format!("per_page must be between 1 and {MAX_PAGE_SIZE}.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        let offset = (page - 1).saturating_mul(per_page);
+        Ok((page, per_page, offset))
+    }
+}
+
+/// Whether a link can currently be followed, and if not, why.
+#[derive(Debug, Serialize, ToSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStatus {
+    Active,
+    Expired,
+    Deleted,
+    Disabled,
+    /// The link was followed as often as it was allowed to be.
+    Exhausted,
+    /// The link was scheduled to start working later.
+    Scheduled,
+}
+
+/// A link as seen by moderators, including links that can't be followed anymore.
+#[derive(Serialize, ToSchema)]
+pub struct AdminLink {
+    pub id: i32,
+    pub code: String,
+    pub short_url: String,
+    pub original_url: String,
+    pub status: LinkStatus,
+    pub created_at: String,
+    pub expires_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled_at: Option<String>,
+    pub visits: i64,
+    /// The API key the link was created with, if any.
+    pub api_key_id: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LinkPage {
+    pub links: Vec<AdminLink>,
+    pub page: i64,
+    pub per_page: i64,
+    /// The number of matching links on all pages.
+    pub total: i64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DomainBan {
+    /// Links to this domain and its subdomains can't be created anymore.
+    pub domain: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BannedDomainResource {
+    pub domain: String,
+    pub reason: Option<String>,
+    pub created_at: String,
+    /// How many existing links to the domain were disabled by the ban.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled_links: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditLogEntry {
+    pub id: i32,
+    /// The admin key that took the action. Empty once the key itself was deleted.
+    pub api_key_id: Option<i32>,
+    pub action: String,
+    /// The short name or domain the action was taken on.
+    pub target: String,
+    pub details: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditLogEntry>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportReason {
+    Phishing,
+    Malware,
+    Spam,
+    Illegal,
+    Other,
+}
+
+impl ReportReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportReason::Phishing => "phishing",
+            ReportReason::Malware => "malware",
+            ReportReason::Spam => "spam",
+            ReportReason::Illegal => "illegal",
+            ReportReason::Other => "other",
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReportInput {
+    pub reason: ReportReason,
+    /// Anything that helps moderators judge the report, at most 1000 characters.
+    pub details: Option<String>,
+}
+
+/// Who sent a report. Reporters are told apart by `identity_hash`.
+pub struct Reporter<'a> {
+    /// The keyed hash of the API key or network of the client, see `keyed_hash`.
+    pub identity_hash: String,
+    pub user_agent: Option<&'a str>,
+    pub api_key_id: Option<i32>,
+}
+
+/// Stores a report about a link, disabling the link once enough different clients reported it.
+pub fn submit_report(
+    code: &str,
+    input: ReportInput,
+    reporter: &Reporter,
+    disable_threshold: Option<u32>,
+    repo: &mut impl ModerationRepository,
+) -> Result<(), AppError> {
+    let details = input
+        .details
+        .as_deref()
+        .filter(|details| !details.is_empty());
+    if details.is_some_and(|details| details.chars().count() > MAX_REPORT_DETAILS_LENGTH) {
+        let error_msg =
+            format!("The details can be at most {MAX_REPORT_DETAILS_LENGTH} characters long.");
+        return Err(AppError::UserInputError(error_msg));
+    }
+    let reporter_agent =
+        reporter.user_agent.map(
+            |agent| match agent.char_indices().nth(MAX_USER_AGENT_LENGTH) {
+                Some((end, _)) => &agent[..end],
+                None => agent,
+            },
+        );
+    let report = NewLinkReport {
+        reason: input.reason.as_str(),
+        details,
+        reporter_hash: &reporter.identity_hash,
+        reporter_agent,
+        api_key_id: reporter.api_key_id,
+    };
+    let name = GeneratedName(code.to_string());
+    let disabled = repo.add_report(&name, &report, disable_threshold)?;
+    if disabled {
+        log::warn!(
+            "The link {} was disabled after being reported.",
+            name.canonical()
+        );
+    }
+    Ok(())
+}
+
+/// An open report together with the link it's about.
+#[derive(Serialize, ToSchema)]
+pub struct AdminReport {
+    pub id: i32,
+    pub reason: String,
+    pub details: Option<String>,
+    pub reporter_agent: Option<String>,
+    /// The API key the report was sent with, if any.
+    pub api_key_id: Option<i32>,
+    pub created_at: String,
+    pub link: AdminLink,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReportPage {
+    pub reports: Vec<AdminReport>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ResolvedReports {
+    /// How many open reports were resolved.
+    pub resolved: usize,
+}
+
+/// Searches and moderates links on behalf of an admin key. Every change is recorded in the
+/// audit log under that key.
+pub struct ModerationService<'a> {
+    base_url: &'a str,
+    admin_key_id: i32,
+}
+
+impl<'a> ModerationService<'a> {
+    pub fn new(base_url: &'a str, admin_key_id: i32) -> Self {
+        Self {
+            base_url,
+            admin_key_id,
+        }
+    }
+
+    pub fn search_links(
+        &self,
+        search: LinkSearch,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<LinkPage, AppError> {
+        let pagination = Pagination {
+            page: search.page,
+            per_page: search.per_page,
+        };
+        let (page, per_page, offset) = pagination.bounds()?;
+        let domain = search.domain.as_deref().map(normalize_domain).transpose()?;
+        let filter = LinkFilter {
+            destination: search.q.filter(|q| !q.is_empty()),
+            domain_pattern: domain.as_deref().map(domain_pattern),
+            name: search.name.map(|name| name.to_lowercase()),
+            created_after: parse_timestamp(search.created_after.as_deref(), "created_after")?,
+            created_before: parse_timestamp(search.created_before.as_deref(), "created_before")?,
+            offset,
+            limit: per_page,
+        };
+        let (found, total) = repo.search_links(&filter)?;
+        Ok(LinkPage {
+            links: found
+                .into_iter()
+                .map(|link| self.to_admin_link(link))
+                .collect(),
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    /// Disabled links can't be followed until they are enabled again.
+    pub fn set_disabled(
+        &self,
+        code: &str,
+        disabled: bool,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<AdminLink, AppError> {
+        let name = GeneratedName(code.to_string());
+        let canonical_name = name.canonical();
+        let action = if disabled {
+            "disable_link"
+        } else {
+            "enable_link"
+        };
+        let audit = self.audit_entry(action, &canonical_name, None);
+        let link = repo.set_link_disabled(&name, disabled, &audit)?;
+        Ok(self.to_admin_link(link))
+    }
+
+    pub fn delete_link(
+        &self,
+        code: &str,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<(), AppError> {
+        let name = GeneratedName(code.to_string());
+        let canonical_name = name.canonical();
+        let audit = self.audit_entry("delete_link", &canonical_name, None);
+        repo.take_down_link(&name, &audit)
+    }
+
+    /// Bans a domain, which also disables the active links that lead to it.
+    pub fn ban_domain(
+        &self,
+        ban: DomainBan,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<BannedDomainResource, AppError> {
+        let domain = normalize_domain(&ban.domain)?;
+        let reason = ban.reason.as_deref().filter(|reason| !reason.is_empty());
+        let new_ban = NewBannedDomain {
+            domain: &domain,
+            reason,
+        };
+        let audit = self.audit_entry("ban_domain", &domain, reason.map(str::to_string));
+        let (banned, disabled) = repo.ban_domain(&new_ban, &domain_pattern(&domain), &audit)?;
+        let mut resource = to_banned_domain_resource(banned);
+        resource.disabled_links = Some(disabled);
+        Ok(resource)
+    }
+
+    /// Lifting a ban doesn't enable the links it disabled, those have to be enabled one by one.
+    pub fn unban_domain(
+        &self,
+        domain: &str,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<(), AppError> {
+        let domain = normalize_domain(domain)?;
+        let audit = self.audit_entry("unban_domain", &domain, None);
+        repo.unban_domain(&domain, &audit)
+    }
+
+    pub fn banned_domains(
+        &self,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<Vec<BannedDomainResource>, AppError> {
+        let banned = repo.banned_domains()?;
+        Ok(banned.into_iter().map(to_banned_domain_resource).collect())
+    }
+
+    pub fn open_reports(
+        &self,
+        pagination: Pagination,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<ReportPage, AppError> {
+        let (page, per_page, offset) = pagination.bounds()?;
+        let (reports, total) = repo.open_reports(offset, per_page)?;
+        Ok(ReportPage {
+            reports: reports
+                .into_iter()
+                .map(|(report, link)| self.to_admin_report(report, link))
+                .collect(),
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    /// Dismisses the open reports about a link without disabling it.
+    pub fn resolve_reports(
+        &self,
+        code: &str,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<ResolvedReports, AppError> {
+        let name = GeneratedName(code.to_string());
+        let canonical_name = name.canonical();
+        let audit = self.audit_entry("resolve_reports", &canonical_name, None);
+        let resolved = repo.resolve_reports(&name, &audit)?;
+        Ok(ResolvedReports { resolved })
+    }
+
+    pub fn audit_log(
+        &self,
+        pagination: Pagination,
+        repo: &mut impl ModerationRepository,
+    ) -> Result<AuditLogPage, AppError> {
+        let (page, per_page, offset) = pagination.bounds()?;
+        let (entries, total) = repo.audit_log(offset, per_page)?;
+        Ok(AuditLogPage {
+            entries: entries.into_iter().map(to_audit_log_entry).collect(),
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    fn audit_entry<'c>(
+        &self,
+        action: &'c str,
+        target: &'c str,
+        details: Option<String>,
+    ) -> NewAuditEntry<'c> {
+        NewAuditEntry {
+            api_key_id: Some(self.admin_key_id),
+            action,
+            target,
+            details,
+        }
+    }
+
+    fn to_admin_report(&self, report: LinkReport, link: Link) -> AdminReport {
+        AdminReport {
+            id: report.id,
+            reason: report.reason,
+            details: report.details,
+            reporter_agent: report.reporter_agent,
+            api_key_id: report.api_key_id,
+            created_at: format_timestamp(report.created_at),
+            link: self.to_admin_link(link),
+        }
+    }
+
+    fn to_admin_link(&self, link: Link) -> AdminLink {
+        AdminLink {
+            id: link.id,
+            status: link_status(&link, SystemTime::now()),
+            short_url: format!("{}{}", self.base_url, link.short_link),
+            code: link.short_link,
+            original_url: link.original_link,
+            created_at: format_timestamp(link.created_at),
+            expires_at: format_timestamp(link.expires_at),
+            deleted_at: link.deleted_at.map(format_timestamp),
+            disabled_at: link.disabled_at.map(format_timestamp),
+            visits: link.visit_count,
+            api_key_id: link.api_key_id,
+        }
+    }
+}
+
+fn link_status(link: &Link, now: SystemTime) -> LinkStatus {
+    if link.deleted_at.is_some() {
+        LinkStatus::Deleted
+    } else if link.disabled_at.is_some() {
+        LinkStatus::Disabled
+    } else if link.expires_at <= now {
+        LinkStatus::Expired
+    } else if link.remaining_visits == Some(0) {
+        LinkStatus::Exhausted
+    } else if link.not_before.is_some_and(|not_before| not_before > now) {
+        LinkStatus::Scheduled
+    } else {
+        LinkStatus::Active
+    }
+}
+
+fn to_banned_domain_resource(banned: BannedDomain) -> BannedDomainResource {
+    BannedDomainResource {
+        domain: banned.domain,
+        reason: banned.reason,
+        created_at: format_timestamp(banned.created_at),
+        disabled_links: None,
+    }
+}
+
+fn to_audit_log_entry(entry: AuditEntry) -> AuditLogEntry {
+    AuditLogEntry {
+        id: entry.id,
+        api_key_id: entry.api_key_id,
+        action: entry.action,
+        target: entry.target,
+        details: entry.details,
+        created_at: format_timestamp(entry.created_at),
+    }
+}
+
+/// Brings a domain into the form `url` gives the host of stored links: lowercase, with
+/// international domains in punycode. A leading `*.` is accepted, subdomains are always
+/// included anyway.
+pub fn normalize_domain(input: &str) -> Result<String, AppError> {
+    let error_msg = format!("'{input}' isn't a valid domain.");
+    let domain = input.trim().trim_start_matches("*.").trim_end_matches('.');
+    let is_bare_host = !domain.is_empty() && !domain.contains(['/', ':', '@', '?', '#']);
+    if !is_bare_host {
+        return Err(AppError::UserInputError(error_msg));
+    }
+    match Host::parse(domain) {
+        Ok(Host::Domain(domain)) => Ok(domain),
+        Ok(Host::Ipv4(address)) => Ok(address.to_string()),
+        _ => Err(AppError::UserInputError(error_msg)),
+    }
+}
+
+/// A host and every domain it's part of, e.g. `a.example.com`, `example.com` and `com`. A host
+/// is banned when any of these is. Trailing dots are dropped, `example.com.` is the same host.
+pub fn domain_suffixes(host: &str) -> Vec<String> {
+    let host = host.trim_end_matches('.').to_lowercase();
+    let mut suffixes = vec![host.clone()];
+    let mut rest = host.as_str();
+    while let Some((_, parent)) = rest.split_once('.') {
+        suffixes.push(parent.to_string());
+        rest = parent;
+    }
+    suffixes
+}
+
+/// A Postgres regular expression matching links to `domain` and its subdomains. The domain
+/// is normalized, so dots are the only characters that need escaping. The host may end in
+/// dots, which don't change where the link leads.
+pub fn domain_pattern(domain: &str) -> String {
+    let domain = domain.replace('.', "\\.");
+    format!("^https?://([^/?#@]*@)?([^/?#@]*\\.)?{domain}\\.*(:[0-9]+)?([/?#]|$)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_normalize_domain() {
+        assert_eq!(normalize_domain(" Example.COM ").unwrap(), "example.com");
+        assert_eq!(normalize_domain("*.example.com").unwrap(), "example.com");
+        assert_eq!(normalize_domain("bücher.de").unwrap(), "xn--bcher-kva.de");
+        assert!(normalize_domain("https://example.com/").is_err());
+        assert!(normalize_domain("").is_err());
+    }
+
+    #[test]
+    fn test_domain_suffixes() {
+        assert_eq!(
+            domain_suffixes("a.Example.com"),
+            vec!["a.example.com", "example.com", "com"]
+        );
+        assert_eq!(domain_suffixes("Example.com."), vec!["example.com", "com"]);
+    }
+
+    #[test]
+    fn test_domain_pattern_escapes_dots() {
+        assert_eq!(
+            domain_pattern("example.com"),
+            "^https?://([^/?#@]*@)?([^/?#@]*\\.)?example\\.com\\.*(:[0-9]+)?([/?#]|$)"
+        );
+    }
+
+    #[test]
+    fn test_pagination_bounds() {
+        let pagination = Pagination {
+            page: Some(3),
+            per_page: Some(20),
+        };
+        assert_eq!(pagination.bounds().unwrap(), (3, 20, 40));
+        assert_eq!(
+            Pagination::default().bounds().unwrap(),
+            (1, DEFAULT_PAGE_SIZE, 0)
+        );
+        let too_large = Pagination {
+            page: None,
+            per_page: Some(MAX_PAGE_SIZE + 1),
+        };
+        assert!(too_large.bounds().is_err());
+    }
+
+    #[test]
+    fn test_link_status() {
+        let now = SystemTime::now();
+        let mut link = Link {
+            id: 1,
+            original_link: "https://example.com/".to_string(),
+            short_link: "aardvarkable1".to_string(),
+            created_at: now,
+            management_token_hash: None,
+            expires_at: now + Duration::from_secs(60),
+            redirect_type: "permanent".to_string(),
+            api_key_id: None,
+            visit_count: 0,
+            deleted_at: None,
+            disabled_at: None,
+            interstitial: false,
+            password_hash: None,
+            remaining_visits: None,
+            not_before: None,
+            sticky_variants: false,
+            forward_query: false,
+            forward_path: false,
+        };
+        assert_eq!(link_status(&link, now), LinkStatus::Active);
+        assert_eq!(link_status(&link, link.expires_at), LinkStatus::Expired);
+        link.disabled_at = Some(now);
+        assert_eq!(link_status(&link, now), LinkStatus::Disabled);
+        link.deleted_at = Some(now);
+        assert_eq!(link_status(&link, now), LinkStatus::Deleted);
+    }
+}
@@ -1,10 +1,30 @@
 use core::fmt;
 use rand::{seq::SliceRandom, Rng};
+use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
+
+use super::filter::NameFilter;
+use super::locale::{transliterate, DEFAULT_LOCALE};
+use crate::config::DEFAULT_DENY_LIST_PATH;
+use crate::errors::AppError;
+
+const DATA_DIR: &str = "data";
+/// How often a name is drawn again before giving up, e.g. because the deny list rejects
+/// almost every word.
+pub const MAX_NAME_ATTEMPTS: usize = 1000;
 
 #[derive(Debug)]
 pub struct GeneratedName(pub String);
 
+impl GeneratedName {
+    /// The form names are stored and looked up in. Names are shown in title case but resolved
+    /// case-insensitively, so someone retyping a link in lowercase still ends up in the right place.
+    pub fn canonical(&self) -> String {
+        self.0.to_lowercase()
+    }
+}
+
 impl fmt::Display for GeneratedName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -12,46 +32,131 @@ impl fmt::Display for GeneratedName {
 }
 
 pub trait NameGeneratorTrait {
-    fn make_random_name(&self, rng: &mut rand::rngs::ThreadRng) -> GeneratedName;
+    /// Makes a name from the word lists of `locale`, which must be a supported locale.
+    fn make_random_name(
+        &self,
+        locale: &str,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<GeneratedName, AppError>;
+    /// Whether a name that wasn't generated (e.g. a custom alias) passes the deny list.
+    fn is_allowed(&self, name: &str) -> bool;
+    fn supports_locale(&self, locale: &str) -> bool;
 }
 
-pub struct NameGenerator {
+/// The word lists of a single locale.
+struct WordPack {
     adjectives: Vec<String>,
     nouns: Vec<String>,
 }
 
+impl WordPack {
+    fn from_dir(dir: &Path) -> Self {
+        let adjectives = read_data(&dir.join("animals.txt"));
+        let nouns = read_data(&dir.join("adjectives.txt"));
+        Self { adjectives, nouns }
+    }
+}
+
+pub struct NameGenerator {
+    packs: HashMap<String, WordPack>,
+    filter: NameFilter,
+}
+
 impl Default for NameGenerator {
     fn default() -> Self {
-        let adjectives = read_data("data/animals.txt");
-        let nouns = read_data("data/adjectives.txt");
-        Self { adjectives, nouns }
+        Self::with_deny_list(DEFAULT_DENY_LIST_PATH)
+    }
+}
+
+impl NameGenerator {
+    /// Loads the English word lists found directly in `data/` as well as every locale pack
+    /// in a subdirectory of it, e.g. `data/nl/` or `data/fr/`.
+    pub fn with_deny_list(deny_list_path: &str) -> Self {
+        let data_dir = Path::new(DATA_DIR);
+        let mut packs = HashMap::new();
+        packs.insert(DEFAULT_LOCALE.to_string(), WordPack::from_dir(data_dir));
+
+        let locale_dirs = fs::read_dir(data_dir)
+            .unwrap_or_else(|_| panic!("Could not read {DATA_DIR}"))
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.join("animals.txt").exists() && path.join("adjectives.txt").exists()
+            });
+
+        for dir in locale_dirs {
+            if let Some(locale) = dir.file_name().and_then(|name| name.to_str()) {
+                log::info!("Loaded the word lists for locale {locale}.");
+                packs.insert(locale.to_lowercase(), WordPack::from_dir(&dir));
+            }
+        }
+        let filter = NameFilter::from_file(deny_list_path);
+        Self { packs, filter }
+    }
+
+    pub fn locales(&self) -> Vec<&str> {
+        let mut locales: Vec<&str> = self.packs.keys().map(|l| l.as_str()).collect();
+        locales.sort();
+        locales
     }
 }
 
-fn read_data(path: &str) -> Vec<String> {
+/// Reads a word list, transliterating every word so generated names stay URL-safe.
+fn read_data(path: &Path) -> Vec<String> {
     fs::read_to_string(path)
-        .unwrap_or_else(|_| panic!("Could not read {path}"))
+        .unwrap_or_else(|_| panic!("Could not read {}", path.display()))
         .lines()
-        .map(|s| s.to_string())
+        .map(transliterate)
+        .filter(|s| !s.is_empty())
         .collect()
 }
 
 impl NameGeneratorTrait for NameGenerator {
-    fn make_random_name(&self, rng: &mut rand::rngs::ThreadRng) -> GeneratedName {
-        // We can safely expect here because we know that the vectors are not empty
-        let random_noun = self
-            .nouns
-            .choose(rng)
-            .expect("There are no nouns to generate from");
-
-        let random_adjective = self
-            .adjectives
-            .choose(rng)
-            .expect("There are no adjectives to generate from");
-
-        let random_number = rng.gen_range(0..1000);
-        let title_adjective = make_title_case(random_adjective);
-        GeneratedName(title_adjective + random_noun + &random_number.to_string())
+    fn make_random_name(
+        &self,
+        locale: &str,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<GeneratedName, AppError> {
+        let pack = self
+            .packs
+            .get(locale)
+            .unwrap_or_else(|| panic!("There are no word lists for locale {locale}"));
+
+        // Names rejected by the deny list are simply drawn again.
+        for _ in 0..MAX_NAME_ATTEMPTS {
+            // We can safely expect here because we know that the vectors are not empty
+            let random_noun = pack
+                .nouns
+                .choose(rng)
+                .expect("There are no nouns to generate from");
+
+            let random_adjective = pack
+                .adjectives
+                .choose(rng)
+                .expect("There are no adjectives to generate from");
+
+            if !self.filter.allows_words(random_adjective, random_noun) {
+                continue;
+            }
+
+            let random_number = rng.gen_range(0..1000);
+            let title_adjective = make_title_case(random_adjective);
+            let name = title_adjective + random_noun + &random_number.to_string();
+            if self.filter.allows_name(&name) {
+                return Ok(GeneratedName(name));
+            }
+        }
+        Err(AppError::InfraError(format!(
+            "No name for locale {locale} passed the deny list after {MAX_NAME_ATTEMPTS} attempts."
+        )))
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.filter.allows_name(name)
+    }
+
+    fn supports_locale(&self, locale: &str) -> bool {
+        self.packs.contains_key(locale)
     }
 }
 
@@ -72,18 +177,80 @@ mod tests {
         assert_eq!(result, "Big");
     }
 
+    #[test]
+    fn test_canonical_name_is_lowercase() {
+        let name = GeneratedName("Aardvarkabandoned123".to_string());
+        assert_eq!(name.canonical(), "aardvarkabandoned123");
+    }
+
     #[test]
     fn test_construct_name_generator() {
         let generator = NameGenerator::default();
-        assert!(!generator.adjectives.is_empty());
-        assert!(!generator.nouns.is_empty());
+        let pack = &generator.packs[DEFAULT_LOCALE];
+        assert!(!pack.adjectives.is_empty());
+        assert!(!pack.nouns.is_empty());
     }
 
     #[test]
     fn test_generate_name() {
         let mut rng = rand::thread_rng();
         let generator = NameGenerator::default();
-        let name = generator.make_random_name(&mut rng);
+        let name = generator
+            .make_random_name(DEFAULT_LOCALE, &mut rng)
+            .unwrap();
         assert!(!name.0.is_empty());
     }
+
+    #[test]
+    fn test_locale_packs_are_loaded() {
+        let generator = NameGenerator::default();
+        assert_eq!(generator.locales(), vec!["en", "fr", "nl"]);
+    }
+
+    #[test]
+    fn test_generated_names_are_url_safe() {
+        let mut rng = rand::thread_rng();
+        let generator = NameGenerator::default();
+        for _ in 0..50 {
+            let name = generator.make_random_name("fr", &mut rng).unwrap();
+            assert!(name
+                .0
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-'));
+        }
+    }
+
+    #[test]
+    fn test_generated_names_respect_deny_list() {
+        let mut rng = rand::thread_rng();
+        let pack = WordPack {
+            adjectives: vec!["Human".to_string(), "Aardvark".to_string()],
+            nouns: vec!["fat".to_string()],
+        };
+        let generator = NameGenerator {
+            packs: HashMap::from([(DEFAULT_LOCALE.to_string(), pack)]),
+            filter: NameFilter::parse("human+fat"),
+        };
+        for _ in 0..50 {
+            let name = generator
+                .make_random_name(DEFAULT_LOCALE, &mut rng)
+                .unwrap();
+            assert!(name.0.starts_with("Aardvarkfat"));
+        }
+    }
+
+    #[test]
+    fn test_generation_gives_up_when_everything_is_denied() {
+        let mut rng = rand::thread_rng();
+        let pack = WordPack {
+            adjectives: vec!["Human".to_string()],
+            nouns: vec!["fat".to_string()],
+        };
+        let generator = NameGenerator {
+            packs: HashMap::from([(DEFAULT_LOCALE.to_string(), pack)]),
+            filter: NameFilter::parse("human+fat"),
+        };
+        let result = generator.make_random_name(DEFAULT_LOCALE, &mut rng);
+        assert!(matches!(result, Err(AppError::InfraError(_))));
+    }
 }
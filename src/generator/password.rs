@@ -0,0 +1,61 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::RngCore;
+
+use crate::errors::AppError;
+
+const MAX_PASSWORD_LENGTH: usize = 128;
+const SALT_LENGTH: usize = 16;
+
+/// Unlike management tokens, passwords are chosen by people and may be guessable, so they're
+/// stored as a salted Argon2 hash in the PHC string format.
+pub fn hash_password(password: &str, rng: &mut rand::rngs::ThreadRng) -> Result<String, AppError> {
+    if password.is_empty() || password.chars().count() > MAX_PASSWORD_LENGTH {
+        let error_msg =
+            format!("The password must be between 1 and {MAX_PASSWORD_LENGTH} characters.");
+        return Err(AppError::UserInputError(error_msg));
+    }
+    let mut salt = [0u8; SALT_LENGTH];
+    rng.fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt)
+        .map_err(|e| AppError::InfraError(format!("The password salt couldn't be encoded: {e}")))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| AppError::InfraError(format!("The password couldn't be hashed: {e}")))?;
+    Ok(hash.to_string())
+}
+
+/// Stored hashes that can't be parsed never match.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_password_verifies() {
+        let mut rng = rand::thread_rng();
+        let hash = hash_password("open sesame", &mut rng).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("open sesame", &hash));
+        assert!(!verify_password("open Sesame", &hash));
+        assert!(!verify_password("open sesame", "not a hash"));
+    }
+
+    #[test]
+    fn test_empty_password_is_rejected() {
+        let mut rng = rand::thread_rng();
+        assert!(matches!(
+            hash_password("", &mut rng),
+            Err(AppError::UserInputError(_))
+        ));
+    }
+}
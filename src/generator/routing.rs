@@ -0,0 +1,477 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use time::OffsetDateTime;
+use url::{form_urlencoded, Url};
+use utoipa::ToSchema;
+
+use crate::errors::AppError;
+
+use super::database::{LinkRule, LinkVariant, NewVariant};
+
+pub const MAX_RULES: usize = 20;
+pub const MAX_VARIANTS: usize = 10;
+const MAX_WEIGHT: u32 = 1000;
+/// The endpoints of a link live below it, so paths starting with these are never forwarded.
+pub const RESERVED_SEGMENTS: [&str; 3] = ["info", "qr", "report"];
+
+/// The operating system a visitor's browser runs on, as far as its user agent tells.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Android,
+    Ios,
+    Windows,
+    Macos,
+    Linux,
+}
+
+impl Platform {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::Android => "android",
+            Platform::Ios => "ios",
+            Platform::Windows => "windows",
+            Platform::Macos => "macos",
+            Platform::Linux => "linux",
+        }
+    }
+
+    pub fn from_user_agent(user_agent: &str) -> Option<Self> {
+        // Android user agents mention Linux and iOS ones mention Mac OS X, so those are checked
+        // first.
+        if user_agent.contains("Android") {
+            Some(Platform::Android)
+        } else if ["iPhone", "iPad", "iPod"]
+            .iter()
+            .any(|device| user_agent.contains(device))
+        {
+            Some(Platform::Ios)
+        } else if user_agent.contains("Windows") {
+            Some(Platform::Windows)
+        } else if user_agent.contains("Macintosh") || user_agent.contains("Mac OS X") {
+            Some(Platform::Macos)
+        } else if user_agent.contains("Linux") {
+            Some(Platform::Linux)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sends visitors that match all of its conditions somewhere else than the link's own
+/// destination. At least one condition is needed.
+#[derive(Deserialize, Serialize, ToSchema, Clone)]
+pub struct RoutingRule {
+    pub platform: Option<Platform>,
+    /// A primary language subtag like `de`, matched against the visitor's most preferred
+    /// language.
+    pub language: Option<String>,
+    /// An ISO 3166-1 alpha-2 country code like `BE`. Needs a GeoIP database on the server.
+    pub country: Option<String>,
+    /// The start of a time window as `HH:MM` in UTC. The window wraps around midnight when it
+    /// ends before it starts.
+    pub from: Option<String>,
+    /// The end of the time window as `HH:MM` in UTC, exclusive.
+    pub until: Option<String>,
+    pub destination: String,
+}
+
+impl RoutingRule {
+    /// Checks the conditions of the rule. The destination is validated by the caller, like
+    /// the destination of any other link.
+    pub fn to_link_rule(&self, destination: String) -> Result<LinkRule, AppError> {
+        let language = match &self.language {
+            Some(language) => {
+                let language = language.trim().to_lowercase();
+                let is_subtag = (2..=3).contains(&language.len())
+                    && language.chars().all(|c| c.is_ascii_lowercase());
+                if !is_subtag {
+                    let error_msg =
+                        format!("'{language}' isn't a primary language subtag like 'en'.");
+                    return Err(AppError::UserInputError(error_msg));
+                }
+                Some(language)
+            }
+            None => None,
+        };
+        let country = match &self.country {
+            Some(country) => {
+                let country = country.trim().to_uppercase();
+                if country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()) {
+                    let error_msg = format!("'{country}' isn't a two letter country code.");
+                    return Err(AppError::UserInputError(error_msg));
+                }
+                Some(country)
+            }
+            None => None,
+        };
+        let (starts_at_minute, ends_at_minute) = match (&self.from, &self.until) {
+            (Some(from), Some(until)) => {
+                let from = parse_time_of_day(from, "from")?;
+                let until = parse_time_of_day(until, "until")?;
+                if from == until {
+                    let error_msg = "The from and until of a rule can't be the same.".to_string();
+                    return Err(AppError::UserInputError(error_msg));
+                }
+                (Some(from), Some(until))
+            }
+            (None, None) => (None, None),
+            _ => {
+                let error_msg = "A rule needs both from and until, or neither.".to_string();
+                return Err(AppError::UserInputError(error_msg));
+            }
+        };
+        let rule = LinkRule {
+            platform: self.platform.map(|platform| platform.as_str().to_string()),
+            language,
+            country,
+            starts_at_minute,
+            ends_at_minute,
+            destination,
+        };
+        if rule.platform.is_none()
+            && rule.language.is_none()
+            && rule.country.is_none()
+            && rule.starts_at_minute.is_none()
+        {
+            let error_msg =
+                "A rule needs at least one of platform, language, country or from and until."
+                    .to_string();
+            return Err(AppError::UserInputError(error_msg));
+        }
+        Ok(rule)
+    }
+}
+
+/// One of the destinations a link splits its visitors between.
+#[derive(Deserialize, Serialize, ToSchema, Clone)]
+pub struct Variant {
+    pub destination: String,
+    /// Visitors get a variant in proportion to its weight, from 1 to 1000.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Variant {
+    /// Checks the weight of the variant. Like for rules, the destination is validated by the
+    /// caller.
+    pub fn to_new_variant(&self, destination: String) -> Result<NewVariant, AppError> {
+        if !(1..=MAX_WEIGHT).contains(&self.weight) {
+            let error_msg = format!("The weight of a variant must be between 1 and {MAX_WEIGHT}.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        Ok(NewVariant {
+            destination,
+            weight: self.weight as i32,
+        })
+    }
+}
+
+/// What the rules of a link are matched against.
+pub struct Visit {
+    pub platform: Option<Platform>,
+    pub language: Option<String>,
+    pub country: Option<String>,
+    /// Minutes since midnight UTC.
+    pub minute_of_day: i32,
+}
+
+/// The destination of the first rule that matches the visit, `None` when the link's own
+/// destination should be used.
+pub fn pick_destination<'a>(rules: &'a [LinkRule], visit: &Visit) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| matches(rule, visit))
+        .map(|rule| rule.destination.as_str())
+}
+
+/// Picks a variant at random by weight. The variant a returning visitor got before is kept, as
+/// long as the link still has it.
+pub fn pick_variant<'a>(
+    variants: &'a [LinkVariant],
+    previous: Option<i32>,
+    rng: &mut impl Rng,
+) -> Option<&'a LinkVariant> {
+    if let Some(previous) = variants.iter().find(|v| Some(v.position) == previous) {
+        return Some(previous);
+    }
+    let total: i32 = variants.iter().map(|variant| variant.weight).sum();
+    if total <= 0 {
+        return None;
+    }
+    let mut roll = rng.gen_range(0..total);
+    variants.iter().find(|variant| {
+        roll -= variant.weight;
+        roll < 0
+    })
+}
+
+/// Adds what a visit brought along to the destination. The path is appended to the path of the
+/// destination, and query parameters are added unless the destination has its own with the same
+/// name. Paths that would climb out of the destination's path or start with a reserved segment
+/// aren't followed.
+pub fn pass_through(
+    destination: &str,
+    path: Option<&str>,
+    query: Option<&str>,
+) -> Result<String, AppError> {
+    if path.is_none() && query.is_none() {
+        return Ok(destination.to_string());
+    }
+    let mut url = Url::parse(destination).map_err(|_| AppError::UnknownPathError)?;
+    if let Some(path) = path {
+        let first = path.split('/').next().unwrap_or_default();
+        if RESERVED_SEGMENTS.contains(&first) {
+            return Err(AppError::UnknownPathError);
+        }
+        // `Url` treats backslashes like slashes and resolves encoded dots, so those can't be
+        // allowed either. The final check catches whatever else it may normalize.
+        if path.contains('\\') || path.split('/').any(is_dot_segment) {
+            return Err(AppError::UnknownPathError);
+        }
+        let base = url.path().trim_end_matches('/').to_string();
+        url.set_path(&format!("{base}/{path}"));
+        if !url.path().starts_with(&format!("{base}/")) {
+            return Err(AppError::UnknownPathError);
+        }
+    }
+    if let Some(query) = query {
+        let own: Vec<String> = url
+            .query_pairs()
+            .map(|(name, _)| name.into_owned())
+            .collect();
+        let added: Vec<_> = form_urlencoded::parse(query.as_bytes())
+            .filter(|(name, _)| !own.iter().any(|own| own == name))
+            .collect();
+        if !added.is_empty() {
+            url.query_pairs_mut().extend_pairs(added);
+        }
+    }
+    Ok(url.to_string())
+}
+
+fn is_dot_segment(segment: &str) -> bool {
+    let segment = segment.to_lowercase().replace("%2e", ".");
+    segment == "." || segment == ".."
+}
+
+/// Looking up the country takes a GeoIP query, which is only needed when a rule asks for it.
+pub fn needs_country(rules: &[LinkRule]) -> bool {
+    rules.iter().any(|rule| rule.country.is_some())
+}
+
+pub fn minute_of_day(now: SystemTime) -> i32 {
+    let now = OffsetDateTime::from(now);
+    i32::from(now.hour()) * 60 + i32::from(now.minute())
+}
+
+fn matches(rule: &LinkRule, visit: &Visit) -> bool {
+    let platform_matches = rule.platform.as_ref().map_or(true, |platform| {
+        visit.platform.map(|p| p.as_str()) == Some(platform)
+    });
+    let language_matches = rule
+        .language
+        .as_ref()
+        .map_or(true, |language| visit.language.as_ref() == Some(language));
+    let country_matches = rule
+        .country
+        .as_ref()
+        .map_or(true, |country| visit.country.as_ref() == Some(country));
+    let time_matches = match (rule.starts_at_minute, rule.ends_at_minute) {
+        (Some(start), Some(end)) if start <= end => (start..end).contains(&visit.minute_of_day),
+        (Some(start), Some(end)) => visit.minute_of_day >= start || visit.minute_of_day < end,
+        _ => true,
+    };
+    platform_matches && language_matches && country_matches && time_matches
+}
+
+fn parse_time_of_day(value: &str, field: &str) -> Result<i32, AppError> {
+    let error = || {
+        let error_msg = format!("The {field} of a rule must be a time like 08:30.");
+        AppError::UserInputError(error_msg)
+    };
+    let (hours, minutes) = value.trim().split_once(':').ok_or_else(error)?;
+    let hours: i32 = hours.parse().map_err(|_| error())?;
+    let minutes: i32 = minutes.parse().map_err(|_| error())?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(error());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: Option<&str>, until: Option<&str>) -> RoutingRule {
+        RoutingRule {
+            platform: None,
+            language: None,
+            country: None,
+            from: from.map(str::to_string),
+            until: until.map(str::to_string),
+            destination: "https://example.com/".to_string(),
+        }
+    }
+
+    fn visit(minute_of_day: i32) -> Visit {
+        Visit {
+            platform: Some(Platform::Ios),
+            language: Some("de".to_string()),
+            country: Some("BE".to_string()),
+            minute_of_day,
+        }
+    }
+
+    #[test]
+    fn test_platform_from_user_agent() {
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15";
+        let android = "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36";
+        let mac = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15";
+        assert_eq!(Platform::from_user_agent(iphone), Some(Platform::Ios));
+        assert_eq!(Platform::from_user_agent(android), Some(Platform::Android));
+        assert_eq!(Platform::from_user_agent(mac), Some(Platform::Macos));
+        assert_eq!(Platform::from_user_agent("curl/8.5.0"), None);
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let mut android = rule(None, None);
+        android.platform = Some(Platform::Android);
+        let mut german = rule(None, None);
+        german.language = Some("DE".to_string());
+        let mut belgian = rule(None, None);
+        belgian.country = Some("be".to_string());
+        let rules: Vec<LinkRule> = [android, german, belgian]
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| rule.to_link_rule(format!("https://example.com/{i}")))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            pick_destination(&rules, &visit(0)),
+            Some("https://example.com/1")
+        );
+        let mut visit = visit(0);
+        visit.language = None;
+        visit.country = Some("NL".to_string());
+        assert_eq!(pick_destination(&rules, &visit), None);
+        assert!(needs_country(&rules));
+    }
+
+    #[test]
+    fn test_time_window_can_wrap_around_midnight() {
+        let day = rule(Some("08:00"), Some("18:00"))
+            .to_link_rule("https://example.com/".to_string())
+            .unwrap();
+        let night = rule(Some("22:00"), Some("06:30"))
+            .to_link_rule("https://example.com/".to_string())
+            .unwrap();
+        assert!(matches(&day, &visit(8 * 60)));
+        assert!(!matches(&day, &visit(18 * 60)));
+        assert!(matches(&night, &visit(23 * 60)));
+        assert!(matches(&night, &visit(6 * 60)));
+        assert!(!matches(&night, &visit(12 * 60)));
+    }
+
+    #[test]
+    fn test_variants_are_picked_by_weight() {
+        let variant = |position, weight| LinkVariant {
+            position,
+            destination: format!("https://example.com/{position}"),
+            weight,
+            visit_count: 0,
+        };
+        let variants = [variant(0, 3), variant(1, 1)];
+        let mut rng = rand::thread_rng();
+        let first = (0..4000)
+            .filter(|_| pick_variant(&variants, None, &mut rng).unwrap().position == 0)
+            .count();
+        assert!((2700..3300).contains(&first), "picked {first} times");
+        let sticky = pick_variant(&variants, Some(1), &mut rng).unwrap();
+        assert_eq!(sticky.position, 1);
+        // A variant that's gone is replaced by a new pick.
+        assert!(pick_variant(&variants, Some(7), &mut rng).is_some());
+        assert!(pick_variant(&[], None, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_pass_through_merges_path_and_query() {
+        let destination = "https://example.com/docs?utm_source=link";
+        assert_eq!(
+            pass_through(destination, Some("guide/intro"), None).unwrap(),
+            "https://example.com/docs/guide/intro?utm_source=link"
+        );
+        assert_eq!(
+            pass_through(destination, None, Some("utm_source=mail&utm_medium=email")).unwrap(),
+            "https://example.com/docs?utm_source=link&utm_medium=email"
+        );
+        assert_eq!(
+            pass_through("https://example.com/", Some("a b"), Some("")).unwrap(),
+            "https://example.com/a%20b"
+        );
+        assert!(pass_through(destination, Some("../admin"), None).is_err());
+    }
+
+    #[test]
+    fn test_pass_through_rejects_reserved_segments() {
+        let destination = "https://example.com/docs";
+        for path in ["info/more", "qr/", "report/abuse"] {
+            assert!(matches!(
+                pass_through(destination, Some(path), None),
+                Err(AppError::UnknownPathError)
+            ));
+        }
+        assert_eq!(
+            pass_through(destination, Some("guide/info"), None).unwrap(),
+            "https://example.com/docs/guide/info"
+        );
+        assert_eq!(
+            pass_through(destination, Some("information"), None).unwrap(),
+            "https://example.com/docs/information"
+        );
+    }
+
+    #[test]
+    fn test_pass_through_stays_below_destination_path() {
+        let destination = "https://example.com/docs/app";
+        for path in [
+            "..\\admin",
+            "%2e%2e/admin",
+            "%2E./admin",
+            ".%2e/admin",
+            "a/../../admin",
+            "a/%2e/b",
+            "a\\b",
+        ] {
+            assert!(
+                pass_through(destination, Some(path), None).is_err(),
+                "{path} was followed"
+            );
+        }
+        assert_eq!(
+            pass_through(destination, Some("v2/..b"), None).unwrap(),
+            "https://example.com/docs/app/v2/..b"
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let destination = || "https://example.com/".to_string();
+        assert!(rule(None, None).to_link_rule(destination()).is_err());
+        assert!(rule(Some("08:00"), None)
+            .to_link_rule(destination())
+            .is_err());
+        assert!(rule(Some("24:00"), Some("08:00"))
+            .to_link_rule(destination())
+            .is_err());
+        let mut country = rule(None, None);
+        country.country = Some("BEL".to_string());
+        assert!(country.to_link_rule(destination()).is_err());
+    }
+}
@@ -1,17 +1,59 @@
 use core::fmt;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use utoipa::ToSchema;
 
 use crate::errors::AppError;
 
 use super::{
-    database::NamesRepository,
-    name_generator::{GeneratedName, NameGeneratorTrait},
+    database::{
+        ApiKey, ApiKeysRepository, Link, LinkChanges, LinkRule, LinkSettings, LinkVariant,
+        NamesRepository, NewVariant, PendingLink, RedirectType,
+    },
+    locale::{primary_language, DEFAULT_LOCALE},
+    moderation::domain_suffixes,
+    name_generator::{GeneratedName, NameGeneratorTrait, MAX_NAME_ATTEMPTS},
+    password::{hash_password, verify_password},
+    routing::{RoutingRule, Variant, MAX_RULES, MAX_VARIANTS},
+    token::{generate_token, hash_token, sign_until, token_matches, verify_signed},
 };
 use url::Url;
 
-#[derive(Serialize)]
+const MIN_ALIAS_LENGTH: usize = 3;
+const MAX_ALIAS_LENGTH: usize = 64;
+/// Routes next to the links, a link with one of these names couldn't be followed.
+const RESERVED_ALIASES: [&str; 1] = ["bulk"];
+/// Typos further away than this aren't worth suggesting.
+const MAX_SUGGESTION_DISTANCE: i32 = 2;
+pub const DEFAULT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const MAX_BULK_LINKS: usize = 500;
+const MAX_VISITS_LIMIT: u32 = 1_000_000;
+pub const MAX_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How long a visitor has to continue from the interstitial page of a protected link.
+const PASS_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Serialize, ToSchema)]
 pub struct OutputLink {
     link: String,
+    /// Only handed out once, when the link is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    management_token: Option<String>,
+    /// A QR code of the link as a data URI, when it was asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qr: Option<String>,
+}
+
+impl OutputLink {
+    pub fn management_token(&self) -> Option<&str> {
+        self.management_token.as_deref()
+    }
+
+    pub fn with_qr(mut self, qr: String) -> Self {
+        self.qr = Some(qr);
+        self
+    }
 }
 
 impl fmt::Display for OutputLink {
@@ -20,10 +62,205 @@ impl fmt::Display for OutputLink {
     }
 }
 
+/// A link as shown to the owner of the API key it was created with.
+#[derive(Serialize, ToSchema)]
+pub struct OwnedLink {
+    link: String,
+    original_link: String,
+}
+
+/// What anyone holding a short link may know about it before following it.
+#[derive(Serialize, ToSchema)]
+pub struct LinkInfo {
+    pub link: String,
+    pub original_link: String,
+    /// RFC 3339 timestamps in UTC.
+    pub created_at: String,
+    pub expires_at: String,
+    pub visits: i64,
+}
+
+/// A link as returned by the versioned API.
+#[derive(Serialize, ToSchema)]
+pub struct LinkResource {
+    /// The name of the link, the last part of `short_url`.
+    pub code: String,
+    pub short_url: String,
+    pub original_url: String,
+    /// RFC 3339 timestamps in UTC.
+    pub created_at: String,
+    pub expires_at: String,
+    pub redirect_type: RedirectType,
+    /// Whether visitors see the destination before they're sent there.
+    pub interstitial: bool,
+    /// Whether visitors have to enter a password before they're sent on.
+    pub password_protected: bool,
+    /// Whether the query string of a visit is passed on to the destination.
+    pub forward_query: bool,
+    /// Whether a path after the name is appended to the destination.
+    pub forward_path: bool,
+    pub visits: i64,
+    /// How often the link may still be followed. Only shown to the owner of the link, and left
+    /// out when there's no limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_visits: Option<i32>,
+    /// When the link starts working, `null` when it worked right away.
+    pub not_before: Option<String>,
+    /// The destinations the link splits its visitors between, with the visits each one got.
+    /// Only filled in when the owner looks up a single link.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<VariantStats>,
+    pub management: LinkManagement,
+    /// A QR code of the link as a data URI, when it was asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr: Option<String>,
+}
+
+/// How one variant of a split link did so far.
+#[derive(Serialize, ToSchema)]
+pub struct VariantStats {
+    pub destination: String,
+    pub weight: i32,
+    pub visits: i64,
+}
+
+impl From<LinkVariant> for VariantStats {
+    fn from(variant: LinkVariant) -> Self {
+        VariantStats {
+            destination: variant.destination,
+            weight: variant.weight,
+            visits: variant.visit_count,
+        }
+    }
+}
+
+/// Details only shown to whoever manages the link.
+#[derive(Default, Serialize, ToSchema)]
+pub struct LinkManagement {
+    /// Needed to edit or delete the link. Only handed out once, when the link is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub management_token: Option<String>,
+    /// The API key the link was created with, shown to the owner of that key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<i32>,
+}
+
+/// Who looks up a link. Its owner, who created it with an API key or holds its management
+/// token, sees how it's doing.
+#[derive(Default)]
+pub struct Viewer<'a> {
+    pub api_key_id: Option<i32>,
+    pub management_token: Option<&'a str>,
+}
+
+impl Viewer<'_> {
+    fn owns(&self, link: &Link) -> bool {
+        let created_with_key = self.api_key_id.is_some() && self.api_key_id == link.api_key_id;
+        let holds_token = match (self.management_token, &link.management_token_hash) {
+            (Some(token), Some(hash)) => token_matches(token, hash),
+            _ => false,
+        };
+        created_with_key || holds_token
+    }
+}
+
+/// How a visitor shows they may follow a protected link.
+#[derive(Clone, Copy)]
+pub enum Unlock<'a> {
+    /// The password they entered.
+    Password(&'a str),
+    /// Handed out on the interstitial page once the password was checked, so the page doesn't
+    /// have to carry the password itself.
+    Pass(&'a str),
+}
+
+/// Everything about a new link that the user can choose besides its destination.
+#[derive(Default)]
+pub struct ShortenOptions<'a> {
+    pub alias: Option<&'a str>,
+    /// A locale requested explicitly, this takes precedence over `accepted_languages`.
+    pub locale: Option<&'a str>,
+    /// The languages from the `Accept-Language` header, most preferred first.
+    pub accepted_languages: Vec<String>,
+    /// Lifetime of the link in seconds, the service's default is used when it's missing.
+    pub ttl: Option<u64>,
+    /// RFC 3339 timestamps of when the link starts and stops working. `not_after` takes the
+    /// place of `ttl`.
+    pub not_before: Option<&'a str>,
+    pub not_after: Option<&'a str>,
+    pub redirect_type: RedirectType,
+    /// The API key the link is created with, so the owner of the key can find it later.
+    pub api_key_id: Option<i32>,
+    /// Shows visitors the destination before sending them there.
+    pub interstitial: bool,
+    /// Visitors have to enter this password before they're sent on.
+    pub password: Option<&'a str>,
+    /// The link stops working after it was followed this often.
+    pub max_visits: Option<u32>,
+    /// Send visitors that match one of them somewhere else, the first match wins.
+    pub rules: &'a [RoutingRule],
+    /// Split the visitors that match no rule between these destinations.
+    pub variants: &'a [Variant],
+    /// Send returning visitors to the variant they got before.
+    pub sticky_variants: bool,
+    /// Pass the query string of a visit on to the destination.
+    pub forward_query: bool,
+    /// Append a path after the name to the destination.
+    pub forward_path: bool,
+}
+
+/// A single entry of a bulk shorten request.
+#[derive(Deserialize, ToSchema)]
+pub struct BulkLink {
+    pub link: String,
+    pub alias: Option<String>,
+    pub ttl: Option<u64>,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    #[serde(default)]
+    pub redirect_type: RedirectType,
+    #[serde(default)]
+    pub interstitial: bool,
+    pub max_visits: Option<u32>,
+    #[serde(default)]
+    pub forward_query: bool,
+    #[serde(default)]
+    pub forward_path: bool,
+}
+
+/// The outcome for a single entry of a bulk shorten request, in the same order as the request.
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum BulkResult {
+    Shortened(OutputLink),
+    Failed { error: String },
+}
+
+/// A bulk entry that passed validation and is waiting for a free name.
+struct BulkCandidate {
+    original: Url,
+    name: GeneratedName,
+    is_alias: bool,
+    settings: LinkSettings,
+    management_token: String,
+}
+
+/// Changes an owner wants to make to an existing link. Fields that are missing stay as they are.
+/// Split links always send visitors to one of their variants, so their `link` can't be changed.
+#[derive(Deserialize, ToSchema)]
+pub struct LinkEdit {
+    pub link: Option<String>,
+    /// The new lifetime in seconds, counted from the moment of the edit or from `not_before`
+    /// when the link doesn't work yet.
+    pub ttl: Option<u64>,
+    pub redirect_type: Option<RedirectType>,
+}
+
 pub trait Shortener {
     fn shorten_name(
         &self,
         name: &mut String,
+        options: &ShortenOptions,
         names_repo: &mut impl NamesRepository,
         rng: &mut rand::rngs::ThreadRng,
     ) -> Result<OutputLink, AppError>;
@@ -31,8 +268,36 @@ pub trait Shortener {
     fn get_original_name(
         &self,
         shortened_link: &str,
+        unlock: Option<Unlock<'_>>,
         names_repo: &mut impl NamesRepository,
-    ) -> Result<String, AppError>;
+    ) -> Result<Link, AppError>;
+
+    fn link_info(
+        &self,
+        shortened_link: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<LinkInfo, AppError>;
+
+    fn suggest_name(
+        &self,
+        shortened_link: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<Option<OutputLink>, AppError>;
+
+    fn edit_link(
+        &self,
+        shortened_link: &str,
+        management_token: &str,
+        edit: LinkEdit,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<(), AppError>;
+
+    fn delete_link(
+        &self,
+        shortened_link: &str,
+        management_token: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<(), AppError>;
 }
 
 pub struct ShortenService<'a, 'b, B>
@@ -41,6 +306,10 @@ where
 {
     base_url: &'a str,
     generator: &'b B,
+    default_ttl: Duration,
+    max_ttl: Duration,
+    /// Without a secret no passes are handed out or accepted.
+    pass_secret: Option<&'a str>,
 }
 
 impl<'a, 'b, B> ShortenService<'a, 'b, B>
@@ -51,9 +320,39 @@ where
         Self {
             base_url,
             generator,
+            default_ttl: DEFAULT_TTL,
+            max_ttl: MAX_TTL,
+            pass_secret: None,
         }
     }
 
+    pub fn with_ttl(mut self, default_ttl: Duration, max_ttl: Duration) -> Self {
+        self.default_ttl = default_ttl;
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    pub fn with_pass_secret(mut self, pass_secret: &'a str) -> Self {
+        self.pass_secret = Some(pass_secret);
+        self
+    }
+
+    /// A pass that follows `link` without its password for a few minutes.
+    pub fn issue_pass(&self, link: &Link) -> Option<String> {
+        let expires_at = SystemTime::now() + PASS_TTL;
+        Some(sign_until(
+            self.pass_secret?,
+            &pass_subject(link),
+            expires_at,
+        ))
+    }
+
+    fn pass_holds(&self, link: &Link, pass: &str) -> bool {
+        self.pass_secret.is_some_and(|secret| {
+            verify_signed(secret, &pass_subject(link), pass, SystemTime::now())
+        })
+    }
+
     fn validate_input(&self, input_link: &mut String) -> Result<Url, AppError> {
         let error_msg =  "You supplied an invalid link. Are you sure its a valid URL? TIP: it should either not have an scheme or be HTTPS".to_string();
         let maybe_url = if input_link.starts_with("https://") {
@@ -66,10 +365,584 @@ where
         maybe_url.map_err(|_| AppError::UserInputError(error_msg))
     }
 
+    /// Rejects links to banned domains or their subdomains.
+    fn check_destination(
+        &self,
+        destination: &Url,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<(), AppError> {
+        let Some(host) = destination.host_str() else {
+            return Ok(());
+        };
+        let banned = names_repo.banned_among(&domain_suffixes(host))?;
+        if banned.is_empty() {
+            return Ok(());
+        }
+        Err(banned_domain_error())
+    }
+
+    fn validate_alias(
+        &self,
+        alias: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<GeneratedName, AppError> {
+        let name = self.check_alias(alias)?;
+        if names_repo.name_exists(&name)? {
+            return Err(alias_taken_error());
+        }
+        Ok(name)
+    }
+
+    /// Checks the format of an alias and the deny list, but not whether it's still free.
+    fn check_alias(&self, alias: &str) -> Result<GeneratedName, AppError> {
+        let valid_chars = alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !(MIN_ALIAS_LENGTH..=MAX_ALIAS_LENGTH).contains(&alias.len()) || !valid_chars {
+            let error_msg = format!("A custom alias must be between {MIN_ALIAS_LENGTH} and {MAX_ALIAS_LENGTH} characters long and may only contain letters, digits, '-' and '_'.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        let is_reserved = RESERVED_ALIASES.contains(&alias.to_lowercase().as_str());
+        if is_reserved || !self.generator.is_allowed(alias) {
+            let error_msg = "This alias isn't allowed. Please pick another one.".to_string();
+            return Err(AppError::UserInputError(error_msg));
+        }
+        Ok(GeneratedName(alias.to_string()))
+    }
+
+    /// Picks the locale to generate a name in. An explicitly requested locale must exist,
+    /// while unsupported `Accept-Language` entries are skipped.
+    fn pick_locale(&self, options: &ShortenOptions) -> Result<String, AppError> {
+        if let Some(requested) = options.locale {
+            // Read like an `Accept-Language` entry, so `FR` and `fr-BE` both mean French.
+            let locale = primary_language(requested);
+            if !self.generator.supports_locale(&locale) {
+                let error_msg = format!("Names can't be generated in the locale '{requested}'.");
+                return Err(AppError::UserInputError(error_msg));
+            }
+            return Ok(locale);
+        }
+        let accepted = options
+            .accepted_languages
+            .iter()
+            .find(|language| self.generator.supports_locale(language));
+        Ok(accepted
+            .map_or(DEFAULT_LOCALE, |language| language.as_str())
+            .to_string())
+    }
+
+    /// The end of a lifetime of `ttl` seconds, or the default lifetime, that begins at `start`.
+    fn expiry_from_ttl(&self, ttl: Option<u64>, start: SystemTime) -> Result<SystemTime, AppError> {
+        let ttl = ttl.map_or(self.default_ttl, Duration::from_secs);
+        if ttl.is_zero() || ttl > self.max_ttl {
+            let max_ttl = self.max_ttl.as_secs();
+            let error_msg = format!("The ttl must be between 1 and {max_ttl} seconds.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        Ok(start + ttl)
+    }
+
+    /// Works out when a new link starts and stops working. The lifetime is counted from when the
+    /// link starts working and can't be longer than the maximum lifetime, however it's scheduled.
+    /// A link can start working at most one maximum lifetime from now.
+    fn schedule(
+        &self,
+        options: &ShortenOptions,
+    ) -> Result<(Option<SystemTime>, SystemTime), AppError> {
+        let now = SystemTime::now();
+        let max_ttl = self.max_ttl.as_secs();
+        let not_before = parse_timestamp(options.not_before, "not_before")?;
+        let starts_at = starts_at(not_before, now);
+        if starts_at > now + self.max_ttl {
+            let error_msg = format!("The not_before can be at most {max_ttl} seconds from now.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        let expires_at = match parse_timestamp(options.not_after, "not_after")? {
+            Some(_) if options.ttl.is_some() => {
+                let error_msg = "Supply either ttl or not_after, not both.".to_string();
+                return Err(AppError::UserInputError(error_msg));
+            }
+            Some(not_after) => {
+                let lifetime = not_after.duration_since(starts_at).unwrap_or_default();
+                if lifetime.is_zero() || lifetime > self.max_ttl {
+                    let error_msg = format!(
+                        "The not_after must be after the link starts working and at most {max_ttl} seconds later."
+                    );
+                    return Err(AppError::UserInputError(error_msg));
+                }
+                not_after
+            }
+            None => self.expiry_from_ttl(options.ttl, starts_at)?,
+        };
+        Ok((not_before, expires_at))
+    }
+
+    /// Checks the routing rules, their destinations have to pass the same checks as the
+    /// destination of the link itself.
+    fn validate_rules(
+        &self,
+        rules: &[RoutingRule],
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<Vec<LinkRule>, AppError> {
+        if rules.len() > MAX_RULES {
+            let error_msg = format!("A link can have at most {MAX_RULES} rules.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        rules
+            .iter()
+            .map(|rule| {
+                let mut destination = rule.destination.clone();
+                let destination = self.validate_input(&mut destination)?;
+                self.check_destination(&destination, names_repo)?;
+                rule.to_link_rule(destination.to_string())
+            })
+            .collect()
+    }
+
+    /// Checks the variants of a split link, their destinations are checked like those of rules.
+    fn validate_variants(
+        &self,
+        variants: &[Variant],
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<Vec<NewVariant>, AppError> {
+        if variants.len() == 1 || variants.len() > MAX_VARIANTS {
+            let error_msg = format!("A link can be split between 2 to {MAX_VARIANTS} variants.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        variants
+            .iter()
+            .map(|variant| {
+                let mut destination = variant.destination.clone();
+                let destination = self.validate_input(&mut destination)?;
+                self.check_destination(&destination, names_repo)?;
+                variant.to_new_variant(destination.to_string())
+            })
+            .collect()
+    }
+
+    fn remaining_visits(&self, max_visits: Option<u32>) -> Result<Option<i32>, AppError> {
+        match max_visits {
+            Some(max_visits @ 1..=MAX_VISITS_LIMIT) => Ok(Some(max_visits as i32)),
+            Some(_) => {
+                let error_msg = format!("The max_visits must be between 1 and {MAX_VISITS_LIMIT}.");
+                Err(AppError::UserInputError(error_msg))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Checks the management token against the hash stored with the link.
+    fn authorize(
+        &self,
+        shortened_link: &str,
+        management_token: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<(GeneratedName, Link), AppError> {
+        let name = GeneratedName(shortened_link.to_string());
+        let link = names_repo.retrieve_link(&name)?;
+        if link.deleted_at.is_some() {
+            return Err(gone_error(&link));
+        }
+        match &link.management_token_hash {
+            Some(hash) if token_matches(management_token, hash) => Ok((name, link)),
+            _ => Err(AppError::ForbiddenError),
+        }
+    }
+
+    /// Looks up a link that can be followed. Names that are still stored but can't be followed
+    /// anymore belong to tombstones, those are reported as gone rather than not found.
+    fn find_active_link(
+        &self,
+        name: &GeneratedName,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<Link, AppError> {
+        match names_repo.retrieve_active_link(name) {
+            Err(AppError::NotFoundError) => {
+                let tombstone = names_repo.retrieve_link(name)?;
+                Err(gone_error(&tombstone))
+            }
+            result => result,
+        }
+    }
+
+    /// The full short link to put in a QR code. The code only holds the name, so links that
+    /// can't be followed yet, such as scheduled or protected ones, get one too.
+    pub fn qr_link(
+        &self,
+        shortened_link: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<String, AppError> {
+        let link = names_repo.retrieve_link(&GeneratedName(shortened_link.to_string()))?;
+        if link.deleted_at.is_some() {
+            return Err(gone_error(&link));
+        }
+        Ok(self.to_output_link(GeneratedName(link.short_link)).link)
+    }
+
+    /// Like `find_active_link`, but links behind a password don't give away their destination.
+    fn find_public_link(
+        &self,
+        name: &GeneratedName,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<Link, AppError> {
+        let link = self.find_active_link(name, names_repo)?;
+        if link.password_hash.is_some() {
+            return Err(AppError::PasswordRequiredError);
+        }
+        Ok(link)
+    }
+
+    pub fn links_for_key(
+        &self,
+        api_key: &ApiKey,
+        names_repo: &mut impl ApiKeysRepository,
+    ) -> Result<Vec<OwnedLink>, AppError> {
+        let owned = names_repo
+            .links_for_key(api_key)?
+            .into_iter()
+            .map(|link| OwnedLink {
+                link: self.to_output_link(GeneratedName(link.short_link)).link,
+                original_link: link.original_link,
+            })
+            .collect();
+        Ok(owned)
+    }
+
+    /// Makes the settings of a new link together with the management token whose hash they hold.
+    fn new_settings(
+        &self,
+        options: &ShortenOptions,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<(LinkSettings, String), AppError> {
+        let (not_before, expires_at) = self.schedule(options)?;
+        let remaining_visits = self.remaining_visits(options.max_visits)?;
+        let password_hash = match options.password {
+            Some(password) => Some(hash_password(password, rng)?),
+            None => None,
+        };
+        let management_token = generate_token(rng);
+        let settings = LinkSettings {
+            management_token_hash: hash_token(&management_token),
+            expires_at,
+            redirect_type: options.redirect_type,
+            api_key_id: options.api_key_id,
+            interstitial: options.interstitial,
+            password_hash,
+            remaining_visits,
+            not_before,
+            rules: Vec::new(),
+            variants: Vec::new(),
+            sticky_variants: options.sticky_variants,
+            forward_query: options.forward_query,
+            forward_path: options.forward_path,
+        };
+        Ok((settings, management_token))
+    }
+
+    /// Stores a new link under `name` and returns the name with the link's management token.
+    fn store_with_token(
+        &self,
+        validated_input: &Url,
+        name: GeneratedName,
+        options: &ShortenOptions,
+        names_repo: &mut impl NamesRepository,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<(GeneratedName, String), AppError> {
+        let (mut settings, management_token) = self.new_settings(options, rng)?;
+        settings.rules = self.validate_rules(options.rules, names_repo)?;
+        settings.variants = self.validate_variants(options.variants, names_repo)?;
+        names_repo.store_name(validated_input, &name, &settings)?;
+        Ok((name, management_token))
+    }
+
+    /// Validates and stores a new link under its alias or a freshly generated name.
+    fn create_name(
+        &self,
+        input: &mut String,
+        options: &ShortenOptions,
+        names_repo: &mut impl NamesRepository,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<(GeneratedName, String), AppError> {
+        let validated_input = self.validate_input(input)?;
+        self.check_destination(&validated_input, names_repo)?;
+        if let Some(alias) = options.alias {
+            let name = self.validate_alias(alias, names_repo)?;
+            return self.store_with_token(&validated_input, name, options, names_repo, rng);
+        }
+        let locale = self.pick_locale(options)?;
+        for _ in 0..MAX_NAME_ATTEMPTS {
+            let generated_name = self.generator.make_random_name(&locale, rng)?;
+            if !names_repo.name_exists(&generated_name)? {
+                return self.store_with_token(
+                    &validated_input,
+                    generated_name,
+                    options,
+                    names_repo,
+                    rng,
+                );
+            }
+        }
+        Err(no_free_name_error())
+    }
+
+    /// Shortens a link like `shorten_name`, but returns the complete link resource.
+    pub fn create_link(
+        &self,
+        input: &mut String,
+        options: &ShortenOptions,
+        names_repo: &mut impl NamesRepository,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<LinkResource, AppError> {
+        let (name, management_token) = self.create_name(input, options, names_repo, rng)?;
+        let link = names_repo.retrieve_link(&name)?;
+        let variants = names_repo.variants(&link)?;
+        let mut resource = self.to_resource(link);
+        resource.variants = variants.into_iter().map(VariantStats::from).collect();
+        // The stored name is lowercase, the response keeps the case it was created with.
+        resource.short_url = self.to_output_link(GeneratedName(name.0.clone())).link;
+        resource.code = name.0;
+        resource.management = LinkManagement {
+            management_token: Some(management_token),
+            api_key_id: options.api_key_id,
+        };
+        Ok(resource)
+    }
+
+    /// Looks up a link that can still be followed. Its owner also sees it while it's scheduled,
+    /// expired or behind a password, along with the remaining visits and how its variants are
+    /// doing.
+    pub fn get_link(
+        &self,
+        code: &str,
+        viewer: &Viewer,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<LinkResource, AppError> {
+        let name = GeneratedName(code.to_string());
+        let link = names_repo.retrieve_link(&name)?;
+        if link.deleted_at.is_some() || !viewer.owns(&link) {
+            let link = self.find_public_link(&name, names_repo)?;
+            let mut resource = self.to_resource(link);
+            resource.remaining_visits = None;
+            return Ok(resource);
+        }
+        let variants = names_repo.variants(&link)?;
+        let mut resource = self.to_resource(link);
+        resource.variants = variants.into_iter().map(VariantStats::from).collect();
+        Ok(resource)
+    }
+
+    /// Lists the links created with an API key, including the key they belong to.
+    pub fn resources_for_key(
+        &self,
+        api_key: &ApiKey,
+        names_repo: &mut impl ApiKeysRepository,
+    ) -> Result<Vec<LinkResource>, AppError> {
+        let resources = names_repo
+            .links_for_key(api_key)?
+            .into_iter()
+            .map(|link| {
+                let api_key_id = link.api_key_id;
+                let mut resource = self.to_resource(link);
+                resource.management.api_key_id = api_key_id;
+                resource
+            })
+            .collect();
+        Ok(resources)
+    }
+
+    /// Management details are left out, callers add the ones the requester may see.
+    fn to_resource(&self, link: Link) -> LinkResource {
+        LinkResource {
+            short_url: self
+                .to_output_link(GeneratedName(link.short_link.clone()))
+                .link,
+            code: link.short_link,
+            original_url: link.original_link,
+            created_at: format_timestamp(link.created_at),
+            expires_at: format_timestamp(link.expires_at),
+            redirect_type: RedirectType::from_db(&link.redirect_type),
+            interstitial: link.interstitial,
+            password_protected: link.password_hash.is_some(),
+            forward_query: link.forward_query,
+            forward_path: link.forward_path,
+            visits: link.visit_count,
+            remaining_visits: link.remaining_visits,
+            not_before: link.not_before.map(format_timestamp),
+            variants: Vec::new(),
+            management: LinkManagement::default(),
+            qr: None,
+        }
+    }
+
+    /// Validates a single bulk entry on its own, without looking at the database.
+    fn prepare_bulk_link(
+        &self,
+        item: BulkLink,
+        locale: &str,
+        defaults: &ShortenOptions,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<BulkCandidate, AppError> {
+        let BulkLink {
+            mut link,
+            alias,
+            ttl,
+            not_before,
+            not_after,
+            redirect_type,
+            interstitial,
+            max_visits,
+            forward_query,
+            forward_path,
+        } = item;
+        let original = self.validate_input(&mut link)?;
+        let options = ShortenOptions {
+            ttl,
+            not_before: not_before.as_deref(),
+            not_after: not_after.as_deref(),
+            redirect_type,
+            interstitial,
+            max_visits,
+            forward_query,
+            forward_path,
+            api_key_id: defaults.api_key_id,
+            ..Default::default()
+        };
+        let (settings, management_token) = self.new_settings(&options, rng)?;
+        let (name, is_alias) = match alias {
+            Some(alias) => (self.check_alias(&alias)?, true),
+            None => (self.generator.make_random_name(locale, rng)?, false),
+        };
+        Ok(BulkCandidate {
+            original,
+            name,
+            is_alias,
+            settings,
+            management_token,
+        })
+    }
+
+    /// Shortens many links at once. Entries that fail validation are reported individually,
+    /// all other entries are stored in a single transaction. Names are checked for collisions
+    /// in batches instead of one query per name.
+    pub fn shorten_bulk(
+        &self,
+        items: Vec<BulkLink>,
+        defaults: &ShortenOptions,
+        names_repo: &mut impl NamesRepository,
+        rng: &mut rand::rngs::ThreadRng,
+    ) -> Result<Vec<BulkResult>, AppError> {
+        if items.is_empty() || items.len() > MAX_BULK_LINKS {
+            let error_msg =
+                format!("A bulk request must contain between 1 and {MAX_BULK_LINKS} links.");
+            return Err(AppError::UserInputError(error_msg));
+        }
+        let locale = self.pick_locale(defaults)?;
+        let mut candidates: Vec<Result<BulkCandidate, AppError>> = items
+            .into_iter()
+            .map(|item| self.prepare_bulk_link(item, &locale, defaults, rng))
+            .collect();
+
+        let hosts: HashSet<&str> = candidates
+            .iter()
+            .filter_map(|c| c.as_ref().ok())
+            .filter_map(|c| c.original.host_str())
+            .collect();
+        let suffixes: Vec<String> = hosts.into_iter().flat_map(domain_suffixes).collect();
+        let banned = names_repo.banned_among(&suffixes)?;
+        if !banned.is_empty() {
+            for candidate in candidates.iter_mut() {
+                let is_banned = candidate.as_ref().is_ok_and(|c| {
+                    c.original.host_str().is_some_and(|host| {
+                        domain_suffixes(host).iter().any(|d| banned.contains(d))
+                    })
+                });
+                if is_banned {
+                    *candidate = Err(banned_domain_error());
+                }
+            }
+        }
+
+        // Aliases can't be regenerated, so the ones that are taken or repeated become errors.
+        let aliases: Vec<&GeneratedName> = candidates
+            .iter()
+            .filter_map(|c| c.as_ref().ok())
+            .filter(|c| c.is_alias)
+            .map(|c| &c.name)
+            .collect();
+        let mut taken = names_repo.existing_names(&aliases)?;
+        for candidate in candidates.iter_mut() {
+            if let Ok(c) = candidate {
+                if c.is_alias && !taken.insert(c.name.canonical()) {
+                    *candidate = Err(alias_taken_error());
+                }
+            }
+        }
+
+        // Generated names are drawn again until none of them collide, with the database or
+        // with another name in the same request.
+        let mut unchecked: Vec<usize> = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.as_ref().is_ok_and(|c| !c.is_alias))
+            .map(|(i, _)| i)
+            .collect();
+        let mut attempts = 0;
+        while !unchecked.is_empty() {
+            if attempts == MAX_NAME_ATTEMPTS {
+                return Err(no_free_name_error());
+            }
+            attempts += 1;
+            let names: Vec<&GeneratedName> = unchecked
+                .iter()
+                .filter_map(|&i| candidates[i].as_ref().ok())
+                .map(|c| &c.name)
+                .collect();
+            let existing = names_repo.existing_names(&names)?;
+            let mut collided = Vec::new();
+            for i in unchecked {
+                if let Ok(c) = &mut candidates[i] {
+                    let canonical_name = c.name.canonical();
+                    if existing.contains(&canonical_name) || !taken.insert(canonical_name) {
+                        c.name = self.generator.make_random_name(&locale, rng)?;
+                        collided.push(i);
+                    }
+                }
+            }
+            unchecked = collided;
+        }
+
+        let mut results = Vec::with_capacity(candidates.len());
+        let mut pending = Vec::new();
+        for candidate in candidates {
+            match candidate {
+                Ok(c) => {
+                    let mut output = self.to_output_link(GeneratedName(c.name.0.clone()));
+                    output.management_token = Some(c.management_token);
+                    results.push(BulkResult::Shortened(output));
+                    pending.push(PendingLink {
+                        original: c.original,
+                        name: c.name,
+                        settings: c.settings,
+                    });
+                }
+                Err(AppError::UserInputError(error)) => results.push(BulkResult::Failed { error }),
+                Err(e) => return Err(e),
+            }
+        }
+        if !pending.is_empty() {
+            names_repo.store_names(&pending)?;
+        }
+        Ok(results)
+    }
+
     fn to_output_link(&self, generated_name: GeneratedName) -> OutputLink {
         let mut link = generated_name.0;
         link.insert_str(0, self.base_url);
-        OutputLink { link }
+        OutputLink {
+            link,
+            management_token: None,
+            qr: None,
+        }
     }
 }
 
@@ -80,108 +953,986 @@ where
     fn shorten_name(
         &self,
         input: &mut String,
+        options: &ShortenOptions,
         names_repo: &mut impl NamesRepository,
         rng: &mut rand::rngs::ThreadRng,
     ) -> Result<OutputLink, AppError> {
-        let validated_input = self.validate_input(input)?;
-        let mut generated_name = self.generator.make_random_name(rng);
-        let mut already_exists = names_repo.name_exists(&generated_name)?;
-        loop {
-            if !already_exists {
-                break;
+        let (name, management_token) = self.create_name(input, options, names_repo, rng)?;
+        let mut output = self.to_output_link(name);
+        output.management_token = Some(management_token);
+        Ok(output)
+    }
+
+    /// Follows a link, the visit is counted by the same query that resolves it. Links behind a
+    /// password are only followed with the right one or a pass, the password is checked with a
+    /// read first: the counting query locks the link, and other visitors shouldn't wait on a
+    /// password hash. Visitors that aren't sent on after all must not be counted, so this runs in
+    /// a transaction that is rolled back for them.
+    fn get_original_name(
+        &self,
+        shortened_link: &str,
+        unlock: Option<Unlock<'_>>,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<Link, AppError> {
+        let name = GeneratedName(shortened_link.to_string());
+        let checked = match unlock {
+            Some(Unlock::Password(password)) => {
+                let link = self.find_active_link(&name, names_repo)?;
+                match &link.password_hash {
+                    Some(hash) if !verify_password(password, hash) => {
+                        return Err(AppError::WrongPasswordError)
+                    }
+                    _ => Some(link.id),
+                }
             }
-            generated_name = self.generator.make_random_name(rng);
-            already_exists = names_repo.name_exists(&generated_name)?;
+            _ => None,
+        };
+        let link = match names_repo.retrieve_original_name(&name) {
+            Err(AppError::NotFoundError) => {
+                let tombstone = names_repo.retrieve_link(&name)?;
+                Err(gone_error(&tombstone))
+            }
+            result => result,
+        }?;
+        match (&link.password_hash, unlock) {
+            (None, _) => Ok(link),
+            (Some(_), None) => Err(AppError::PasswordRequiredError),
+            // The name went to another link in between, the password was checked for the old one.
+            (Some(_), Some(Unlock::Password(_))) if checked != Some(link.id) => {
+                Err(AppError::WrongPasswordError)
+            }
+            (Some(_), Some(Unlock::Password(_))) => Ok(link),
+            (Some(_), Some(Unlock::Pass(pass))) if self.pass_holds(&link, pass) => Ok(link),
+            // The pass expired or wasn't made for this link, the password has to be entered again.
+            (Some(_), Some(Unlock::Pass(_))) => Err(AppError::PasswordRequiredError),
         }
-        names_repo.store_name(&validated_input, &generated_name)?;
-        Ok(self.to_output_link(generated_name))
     }
 
-    fn get_original_name(
+    fn link_info(
         &self,
         shortened_link: &str,
         names_repo: &mut impl NamesRepository,
-    ) -> Result<String, AppError> {
+    ) -> Result<LinkInfo, AppError> {
+        let generated_name = GeneratedName(shortened_link.to_string());
+        let link = self.find_public_link(&generated_name, names_repo)?;
+        Ok(LinkInfo {
+            link: self.to_output_link(GeneratedName(link.short_link)).link,
+            original_link: link.original_link,
+            created_at: format_timestamp(link.created_at),
+            expires_at: format_timestamp(link.expires_at),
+            visits: link.visit_count,
+        })
+    }
+
+    fn suggest_name(
+        &self,
+        shortened_link: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<Option<OutputLink>, AppError> {
         let generated_name = GeneratedName(shortened_link.to_string());
-        names_repo.retrieve_original_name(&generated_name)
+        let closest = names_repo.closest_name(&generated_name, MAX_SUGGESTION_DISTANCE)?;
+        Ok(closest.map(|name| self.to_output_link(GeneratedName(name))))
+    }
+
+    fn edit_link(
+        &self,
+        shortened_link: &str,
+        management_token: &str,
+        edit: LinkEdit,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<(), AppError> {
+        let LinkEdit {
+            link,
+            ttl,
+            redirect_type,
+        } = edit;
+        if link.is_none() && ttl.is_none() && redirect_type.is_none() {
+            let error_msg =
+                "Supply at least one of link, ttl or redirect_type to change.".to_string();
+            return Err(AppError::UserInputError(error_msg));
+        }
+        let (name, stored) = self.authorize(shortened_link, management_token, names_repo)?;
+        if link.is_some() && !names_repo.variants(&stored)?.is_empty() {
+            let error_msg = "This link is split between variants and doesn't use its own destination. Create a new link to change the variants.".to_string();
+            return Err(AppError::UserInputError(error_msg));
+        }
+        let original_link = match link {
+            Some(mut link) => {
+                let destination = self.validate_input(&mut link)?;
+                self.check_destination(&destination, names_repo)?;
+                Some(destination.to_string())
+            }
+            None => None,
+        };
+        // Counting from the start keeps a link that doesn't work yet from expiring before it does.
+        let expires_at = match ttl {
+            Some(_) => {
+                let start = starts_at(stored.not_before, SystemTime::now());
+                Some(self.expiry_from_ttl(ttl, start)?)
+            }
+            None => None,
+        };
+        let changes = LinkChanges {
+            original_link,
+            expires_at,
+            redirect_type: redirect_type.map(|r| r.as_str().to_string()),
+        };
+        names_repo.update_link(&name, &changes)
+    }
+
+    fn delete_link(
+        &self,
+        shortened_link: &str,
+        management_token: &str,
+        names_repo: &mut impl NamesRepository,
+    ) -> Result<(), AppError> {
+        let (name, _) = self.authorize(shortened_link, management_token, names_repo)?;
+        names_repo.delete_link(&name)
     }
 }
 
+/// When a link starts working, `not_before` unless that already passed.
+fn starts_at(not_before: Option<SystemTime>, now: SystemTime) -> SystemTime {
+    not_before.map_or(now, |not_before| not_before.max(now))
+}
+
+/// Formats a timestamp as RFC 3339 in UTC, to the second.
+pub fn format_timestamp(timestamp: SystemTime) -> String {
+    let timestamp = OffsetDateTime::from(timestamp);
+    timestamp
+        .replace_nanosecond(0)
+        .unwrap_or(timestamp)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+pub fn parse_timestamp(value: Option<&str>, field: &str) -> Result<Option<SystemTime>, AppError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let timestamp = OffsetDateTime::parse(value, &Rfc3339).map_err(|_| {
+        let error_msg = format!("{field} must be an RFC 3339 timestamp like 2024-05-10T17:02:19Z.");
+        AppError::UserInputError(error_msg)
+    })?;
+    Ok(Some(timestamp.into()))
+}
+
+/// What a pass is signed for. The id keeps passes from carrying over to a later link with the same
+/// name.
+fn pass_subject(link: &Link) -> String {
+    format!("pass:{}:{}", link.id, link.short_link)
+}
+
+/// Explains why a tombstoned, disabled or scheduled link can't be followed (anymore). Links a
+/// moderator took down are disabled tombstones, those keep the warning.
+fn gone_error(tombstone: &Link) -> AppError {
+    if tombstone.disabled_at.is_some() {
+        return AppError::DisabledError;
+    }
+    let now = SystemTime::now();
+    if let Some(not_before) = tombstone.not_before {
+        if tombstone.deleted_at.is_none() && not_before > now && tombstone.expires_at > now {
+            let error_msg = format!(
+                "This link isn't active yet, it starts working on {}.",
+                format_timestamp(not_before)
+            );
+            return AppError::NotYetActiveError(error_msg);
+        }
+    }
+    let error_msg = match tombstone.deleted_at {
+        Some(deleted_at) => format!(
+            "This link was removed by its owner on {}.",
+            format_timestamp(deleted_at)
+        ),
+        None if tombstone.remaining_visits == Some(0) => {
+            "This link was already followed as often as it was allowed to be.".to_string()
+        }
+        None => format!(
+            "This link expired on {}.",
+            format_timestamp(tombstone.expires_at)
+        ),
+    };
+    AppError::GoneError(error_msg)
+}
+
+fn banned_domain_error() -> AppError {
+    let error_msg = "Links to this domain aren't allowed.".to_string();
+    AppError::UserInputError(error_msg)
+}
+
+fn alias_taken_error() -> AppError {
+    let error_msg = "This alias is already taken. Please pick another one.".to_string();
+    AppError::UserInputError(error_msg)
+}
+
+fn no_free_name_error() -> AppError {
+    AppError::InfraError(format!(
+        "No free name was found after {MAX_NAME_ATTEMPTS} attempts."
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::generator::routing::Platform;
     use crate::NameGenerator;
-    use rand::Rng;
+    use std::collections::HashSet;
+    use std::sync::OnceLock;
+
+    const BASE_URL: &str = "http://localhost:8080/";
+
+    /// A service with the default settings. The word lists are only loaded once for all tests.
+    fn service() -> ShortenService<'static, 'static, NameGenerator> {
+        static GENERATOR: OnceLock<NameGenerator> = OnceLock::new();
+        ShortenService::new(BASE_URL, GENERATOR.get_or_init(NameGenerator::default))
+    }
+
+    /// Fails unless `result` is a `UserInputError` whose message mentions `expected`.
+    #[track_caller]
+    fn assert_input_error<T>(result: Result<T, AppError>, expected: &str) {
+        match result {
+            Err(AppError::UserInputError(message)) => assert!(
+                message.contains(expected),
+                "expected an error about '{expected}', got '{message}'"
+            ),
+            Err(error) => panic!("expected an input error, got {error:?}"),
+            Ok(_) => panic!("expected an input error, got a result"),
+        }
+    }
+
+    fn mock_link() -> Link {
+        Link {
+            id: 1,
+            original_link: "https://localhost:8080/".to_string(),
+            short_link: "aardvarkable1".to_string(),
+            created_at: SystemTime::now(),
+            management_token_hash: Some(hash_token("secret")),
+            expires_at: SystemTime::now() + DEFAULT_TTL,
+            redirect_type: RedirectType::Permanent.as_str().to_string(),
+            api_key_id: None,
+            visit_count: 3,
+            deleted_at: None,
+            disabled_at: None,
+            interstitial: false,
+            password_hash: None,
+            remaining_visits: None,
+            not_before: None,
+            sticky_variants: false,
+            forward_query: false,
+            forward_path: false,
+        }
+    }
+
+    /// The names the mock always reports as taken.
+    const TAKEN_NAMES: [&str; 1] = ["taken"];
+
+    /// Besides `TAKEN_NAMES`, the first `collisions` names that are checked are taken as well.
+    #[derive(Default)]
+    struct MockNamesRepository {
+        collisions: usize,
+    }
+
+    impl MockNamesRepository {
+        fn colliding(collisions: usize) -> Self {
+            Self { collisions }
+        }
+
+        fn is_taken(&mut self, name: &GeneratedName) -> bool {
+            if TAKEN_NAMES.contains(&name.canonical().as_str()) {
+                return true;
+            }
+            let collides = self.collisions > 0;
+            self.collisions = self.collisions.saturating_sub(1);
+            collides
+        }
+    }
 
-    struct MockNamesRepository {}
     impl NamesRepository for MockNamesRepository {
         fn store_name(
             &mut self,
             _original: &Url,
             _generated: &GeneratedName,
+            _settings: &LinkSettings,
+        ) -> Result<(), AppError> {
+            Ok(())
+        }
+
+        fn store_names(&mut self, _pending: &[PendingLink]) -> Result<(), AppError> {
+            Ok(())
+        }
+
+        fn existing_names(
+            &mut self,
+            names: &[&GeneratedName],
+        ) -> Result<HashSet<String>, AppError> {
+            let taken = names
+                .iter()
+                .filter(|name| self.is_taken(name))
+                .map(|name| name.canonical());
+            Ok(taken.collect())
+        }
+
+        fn banned_among(&mut self, domains: &[String]) -> Result<HashSet<String>, AppError> {
+            let banned = domains.iter().filter(|d| *d == "banned.example");
+            Ok(banned.cloned().collect())
+        }
+
+        fn name_exists(&mut self, name: &GeneratedName) -> Result<bool, AppError> {
+            Ok(self.is_taken(name))
+        }
+
+        fn retrieve_active_link(&mut self, name: &GeneratedName) -> Result<Link, AppError> {
+            let link = self.retrieve_link(name)?;
+            let scheduled = link
+                .not_before
+                .is_some_and(|start| start > SystemTime::now());
+            if scheduled || link.deleted_at.is_some() {
+                return Err(AppError::NotFoundError);
+            }
+            Ok(link)
+        }
+
+        fn retrieve_link(&mut self, name: &GeneratedName) -> Result<Link, AppError> {
+            let mut link = mock_link();
+            match name.0.as_str() {
+                "protected" => {
+                    let mut rng = rand::thread_rng();
+                    link.password_hash = Some(hash_password("hunter2", &mut rng)?);
+                }
+                "limited" => link.remaining_visits = Some(2),
+                "scheduled" => link.not_before = Some(SystemTime::now() + Duration::from_secs(60)),
+                "deleted" => link.deleted_at = Some(SystemTime::now()),
+                _ => (),
+            }
+            Ok(link)
+        }
+
+        fn variants(&mut self, _link: &Link) -> Result<Vec<LinkVariant>, AppError> {
+            Ok(Vec::new())
+        }
+
+        fn record_variant_visit(&mut self, _link: &Link, _position: i32) -> Result<(), AppError> {
+            Ok(())
+        }
+
+        fn routing_rules(&mut self, _link: &Link) -> Result<Vec<LinkRule>, AppError> {
+            Ok(Vec::new())
+        }
+
+        fn update_link(
+            &mut self,
+            _name: &GeneratedName,
+            _changes: &LinkChanges,
         ) -> Result<(), AppError> {
             Ok(())
         }
 
-        fn name_exists(&mut self, _name: &GeneratedName) -> Result<bool, AppError> {
-            let mut rng = rand::thread_rng();
-            let exists = rng.gen_bool(0.5);
-            Ok(exists)
+        fn retrieve_original_name(&mut self, name: &GeneratedName) -> Result<Link, AppError> {
+            self.retrieve_active_link(name)
         }
 
-        fn retrieve_original_name(&mut self, _name: &GeneratedName) -> Result<String, AppError> {
-            Ok("".to_string())
+        fn delete_link(&mut self, _name: &GeneratedName) -> Result<(), AppError> {
+            Ok(())
+        }
+
+        fn closest_name(
+            &mut self,
+            _name: &GeneratedName,
+            _max_distance: i32,
+        ) -> Result<Option<String>, AppError> {
+            Ok(Some("aardvarkable1".to_string()))
         }
     }
 
     #[test]
     fn test_validate_input() {
-        let generator = NameGenerator::default();
-        let shortener = ShortenService::new("http://localhost:8080/", &generator);
+        let shortener = service();
         let result = shortener.validate_input(&mut "https://localhost:8080/".to_string());
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_http_is_invalid() {
-        let generator = NameGenerator::default();
-        let shortener = ShortenService::new("http://localhost:8080/", &generator);
+        let shortener = service();
         let result = shortener.validate_input(&mut "http://localhost:8080/".to_string());
         assert!(result.is_err());
     }
 
     #[test]
     fn test_validate_input_invalid() {
-        let generator = NameGenerator::default();
-
-        let shortener = ShortenService::new("http://localhost:8080/", &generator);
+        let shortener = service();
         let result = shortener.validate_input(&mut "google.com".to_string());
         assert!(result.is_err());
     }
 
     #[test]
     fn test_to_output_link() {
-        let generator = NameGenerator::default();
-        let shortener = ShortenService::new("http://localhost:8080/", &generator);
+        let shortener = service();
         let generated_name = GeneratedName("test".to_string());
         let result = shortener.to_output_link(generated_name);
         assert_eq!(result.link, "http://localhost:8080/test");
     }
 
+    #[test]
+    fn test_suggestion_is_a_full_link() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let result = shortener.suggest_name("aardvarkable2", &mut repo).unwrap();
+        assert_eq!(
+            result.map(|link| link.to_string()),
+            Some("http://localhost:8080/aardvarkable1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_link_info() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let info = shortener.link_info("aardvarkable1", &mut repo).unwrap();
+        assert_eq!(info.link, "http://localhost:8080/aardvarkable1");
+        assert_eq!(info.original_link, "https://localhost:8080/");
+        assert_eq!(info.visits, 3);
+        assert!(info.expires_at > info.created_at);
+    }
+
+    #[test]
+    fn test_password_protected_link_needs_password() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        for result in [
+            shortener
+                .get_original_name("protected", None, &mut repo)
+                .err(),
+            shortener.link_info("protected", &mut repo).err(),
+        ] {
+            assert!(matches!(result, Some(AppError::PasswordRequiredError)));
+        }
+        assert!(matches!(
+            shortener.get_original_name("protected", Some(Unlock::Password("hunter3")), &mut repo),
+            Err(AppError::WrongPasswordError)
+        ));
+        let link = shortener
+            .get_original_name("protected", Some(Unlock::Password("hunter2")), &mut repo)
+            .unwrap();
+        assert_eq!(link.original_link, "https://localhost:8080/");
+    }
+
+    #[test]
+    fn test_pass_follows_a_protected_link_without_its_password() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service().with_pass_secret("secret");
+        let protected = repo
+            .retrieve_active_link(&GeneratedName("protected".to_string()))
+            .unwrap();
+        let pass = shortener.issue_pass(&protected).unwrap();
+        let link = shortener
+            .get_original_name("protected", Some(Unlock::Pass(&pass)), &mut repo)
+            .unwrap();
+        assert_eq!(link.original_link, "https://localhost:8080/");
+
+        let other_secret = service().with_pass_secret("other");
+        let without_secret = service();
+        assert!(without_secret.issue_pass(&protected).is_none());
+        for shortener in [other_secret, without_secret] {
+            assert!(matches!(
+                shortener.get_original_name("protected", Some(Unlock::Pass(&pass)), &mut repo),
+                Err(AppError::PasswordRequiredError)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_qr_link_only_needs_a_stored_link() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let link = shortener.qr_link("scheduled", &mut repo).unwrap();
+        assert_eq!(link, "http://localhost:8080/aardvarkable1");
+        assert!(matches!(
+            shortener.qr_link("deleted", &mut repo),
+            Err(AppError::GoneError(_))
+        ));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(86_490_250);
+        assert_eq!(format_timestamp(timestamp), "1970-01-02T00:01:30Z");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let parsed = parse_timestamp(Some("1970-01-02T00:00:00Z"), "created_after").unwrap();
+        assert_eq!(
+            parsed,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(86400))
+        );
+        assert_input_error(
+            parse_timestamp(Some("yesterday"), "created_after"),
+            "created_after must be an RFC 3339 timestamp",
+        );
+    }
+
+    #[test]
+    fn test_schedule_stays_within_maximum_lifetime() {
+        let shortener = service().with_ttl(Duration::from_secs(3600), Duration::from_secs(7200));
+        let now = SystemTime::now();
+        let in_an_hour = format_timestamp(now + Duration::from_secs(3600));
+        let in_two_hours = format_timestamp(now + Duration::from_secs(7200));
+        let in_three_hours = format_timestamp(now + Duration::from_secs(10800));
+        let scheduled = |not_before, not_after, ttl| {
+            let options = ShortenOptions {
+                not_before,
+                not_after,
+                ttl,
+                ..Default::default()
+            };
+            shortener.schedule(&options)
+        };
+
+        let (not_before, expires_at) = scheduled(None, Some(&in_an_hour), None).unwrap();
+        assert_eq!(not_before, None);
+        assert_eq!(format_timestamp(expires_at), in_an_hour);
+        assert_input_error(scheduled(None, Some(&in_three_hours), None), "not_after");
+        assert_input_error(
+            scheduled(None, Some("1970-01-01T00:00:00Z"), None),
+            "not_after",
+        );
+        assert_input_error(
+            scheduled(None, Some(&in_an_hour), Some(60)),
+            "either ttl or not_after",
+        );
+
+        // Lifetimes are counted from when the link starts working.
+        let (_, expires_at) = scheduled(Some(&in_an_hour), None, None).unwrap();
+        assert_eq!(format_timestamp(expires_at), in_two_hours);
+        let (not_before, expires_at) = scheduled(Some(&in_an_hour), None, Some(7200)).unwrap();
+        assert_eq!(not_before.map(format_timestamp), Some(in_an_hour.clone()));
+        assert_eq!(format_timestamp(expires_at), in_three_hours);
+        assert_input_error(
+            scheduled(Some(&in_an_hour), Some(&in_an_hour), None),
+            "not_after",
+        );
+        assert_input_error(scheduled(Some(&in_three_hours), None, None), "not_before");
+    }
+
+    #[test]
+    fn test_create_link_returns_resource_with_token() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let mut rng = rand::thread_rng();
+        let options = ShortenOptions {
+            alias: Some("MyAlias"),
+            api_key_id: Some(7),
+            ..Default::default()
+        };
+        let mut input = "localhost:8080/".to_string();
+        let resource = shortener
+            .create_link(&mut input, &options, &mut repo, &mut rng)
+            .unwrap();
+        assert_eq!(resource.code, "MyAlias");
+        assert_eq!(resource.redirect_type, RedirectType::Permanent);
+        assert!(resource.management.management_token.is_some());
+        assert_eq!(resource.management.api_key_id, Some(7));
+    }
+
+    #[test]
+    fn test_get_link_hides_management_details() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let viewer = Viewer::default();
+        let resource = shortener
+            .get_link("aardvarkable1", &viewer, &mut repo)
+            .unwrap();
+        assert_eq!(resource.short_url, "http://localhost:8080/aardvarkable1");
+        assert!(resource.management.management_token.is_none());
+        assert!(resource.management.api_key_id.is_none());
+    }
+
+    #[test]
+    fn test_get_link_shows_remaining_visits_to_owner() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let visitors = [
+            (Viewer::default(), None),
+            (
+                Viewer {
+                    api_key_id: Some(7),
+                    management_token: Some("wrong"),
+                },
+                None,
+            ),
+            (
+                Viewer {
+                    api_key_id: None,
+                    management_token: Some("secret"),
+                },
+                Some(2),
+            ),
+        ];
+        for (viewer, remaining_visits) in visitors {
+            let resource = shortener.get_link("limited", &viewer, &mut repo).unwrap();
+            assert_eq!(resource.remaining_visits, remaining_visits);
+        }
+    }
+
+    #[test]
+    fn test_owner_sees_own_protected_and_scheduled_links() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let owner = Viewer {
+            api_key_id: None,
+            management_token: Some("secret"),
+        };
+        for code in ["protected", "scheduled"] {
+            let resource = shortener.get_link(code, &owner, &mut repo).unwrap();
+            assert_eq!(resource.short_url, "http://localhost:8080/aardvarkable1");
+        }
+        let visitor = Viewer::default();
+        assert!(matches!(
+            shortener.get_link("protected", &visitor, &mut repo),
+            Err(AppError::PasswordRequiredError)
+        ));
+        assert!(matches!(
+            shortener.get_link("scheduled", &visitor, &mut repo),
+            Err(AppError::NotYetActiveError(_))
+        ));
+    }
+
+    #[test]
+    fn test_gone_error_explains_tombstone() {
+        let mut link = mock_link();
+        link.expires_at = SystemTime::UNIX_EPOCH + Duration::from_secs(86400);
+        let AppError::GoneError(expired) = gone_error(&link) else {
+            panic!("expected a gone error");
+        };
+        assert_eq!(expired, "This link expired on 1970-01-02T00:00:00Z.");
+
+        link.deleted_at = Some(SystemTime::UNIX_EPOCH);
+        let AppError::GoneError(deleted) = gone_error(&link) else {
+            panic!("expected a gone error");
+        };
+        assert_eq!(
+            deleted,
+            "This link was removed by its owner on 1970-01-01T00:00:00Z."
+        );
+
+        link.deleted_at = None;
+        link.remaining_visits = Some(0);
+        let AppError::GoneError(used_up) = gone_error(&link) else {
+            panic!("expected a gone error");
+        };
+        assert_eq!(
+            used_up,
+            "This link was already followed as often as it was allowed to be."
+        );
+
+        link.disabled_at = Some(SystemTime::UNIX_EPOCH);
+        assert!(matches!(gone_error(&link), AppError::DisabledError));
+        link.deleted_at = Some(SystemTime::UNIX_EPOCH);
+        assert!(matches!(gone_error(&link), AppError::DisabledError));
+    }
+
+    #[test]
+    fn test_pick_locale_skips_unsupported_languages() {
+        let shortener = service();
+        let options = ShortenOptions {
+            accepted_languages: vec!["de".to_string(), "nl".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(shortener.pick_locale(&options).unwrap(), "nl");
+    }
+
+    #[test]
+    fn test_unsupported_explicit_locale_is_rejected() {
+        let shortener = service();
+        let options = ShortenOptions {
+            locale: Some("de"),
+            ..Default::default()
+        };
+        assert_input_error(shortener.pick_locale(&options), "locale 'de'");
+    }
+
+    #[test]
+    fn test_explicit_locale_is_normalized() {
+        let shortener = service();
+        for requested in [" FR ", "fr-BE"] {
+            let options = ShortenOptions {
+                locale: Some(requested),
+                ..Default::default()
+            };
+            assert_eq!(shortener.pick_locale(&options).unwrap(), "fr");
+        }
+    }
+
+    #[test]
+    fn test_ttl_above_maximum_is_rejected() {
+        let shortener = service().with_ttl(Duration::from_secs(60), Duration::from_secs(120));
+        let now = SystemTime::now();
+        assert!(shortener.expiry_from_ttl(None, now).is_ok());
+        assert!(shortener.expiry_from_ttl(Some(120), now).is_ok());
+        assert_input_error(shortener.expiry_from_ttl(Some(121), now), "ttl");
+        assert_input_error(shortener.expiry_from_ttl(Some(0), now), "ttl");
+    }
+
+    #[test]
+    fn test_max_visits_must_be_positive() {
+        let shortener = service();
+        assert_eq!(shortener.remaining_visits(None).unwrap(), None);
+        assert_eq!(shortener.remaining_visits(Some(1)).unwrap(), Some(1));
+        assert_input_error(shortener.remaining_visits(Some(0)), "max_visits");
+        assert_input_error(
+            shortener.remaining_visits(Some(MAX_VISITS_LIMIT + 1)),
+            "max_visits",
+        );
+    }
+
+    #[test]
+    fn test_shortened_link_has_management_token() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let mut rng = rand::thread_rng();
+        let result = shortener
+            .shorten_name(
+                &mut "https://localhost:8080/".to_string(),
+                &ShortenOptions::default(),
+                &mut repo,
+                &mut rng,
+            )
+            .unwrap();
+        assert!(result.management_token.is_some());
+    }
+
+    #[test]
+    fn test_links_to_banned_domains_are_rejected() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let mut rng = rand::thread_rng();
+        let result = shortener.shorten_name(
+            &mut "https://www.Banned.example/login".to_string(),
+            &ShortenOptions::default(),
+            &mut repo,
+            &mut rng,
+        );
+        assert_input_error(result, "domain aren't allowed");
+        let result = shortener.shorten_name(
+            &mut "https://banned.example./login".to_string(),
+            &ShortenOptions::default(),
+            &mut repo,
+            &mut rng,
+        );
+        assert_input_error(result, "domain aren't allowed");
+        let items = vec![
+            bulk_link("https://localhost:8080/", None),
+            bulk_link("banned.example", None),
+        ];
+        let results = shortener
+            .shorten_bulk(items, &ShortenOptions::default(), &mut repo, &mut rng)
+            .unwrap();
+        assert!(matches!(results[0], BulkResult::Shortened(_)));
+        assert!(matches!(results[1], BulkResult::Failed { .. }));
+    }
+
+    #[test]
+    fn test_rules_to_banned_domains_are_rejected() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let mut rng = rand::thread_rng();
+        let rules = [RoutingRule {
+            platform: Some(Platform::Android),
+            language: None,
+            country: None,
+            from: None,
+            until: None,
+            destination: "https://banned.example/app".to_string(),
+        }];
+        let options = ShortenOptions {
+            rules: &rules,
+            ..Default::default()
+        };
+        let result = shortener.shorten_name(
+            &mut "https://localhost:8080/".to_string(),
+            &options,
+            &mut repo,
+            &mut rng,
+        );
+        assert_input_error(result, "domain aren't allowed");
+    }
+
+    #[test]
+    fn test_split_needs_two_valid_variants() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let variant = |destination: &str, weight| Variant {
+            destination: destination.to_string(),
+            weight,
+        };
+        let valid = [variant("https://a.example/", 3), variant("b.example", 1)];
+        let stored = shortener.validate_variants(&valid, &mut repo).unwrap();
+        assert_eq!(stored[1].destination, "https://b.example/");
+        assert_eq!(stored[0].weight, 3);
+        let single = [variant("https://a.example/", 1)];
+        assert_input_error(
+            shortener.validate_variants(&single, &mut repo),
+            "2 to 10 variants",
+        );
+        let weightless = [variant("https://a.example/", 0), variant("b.example", 1)];
+        assert_input_error(
+            shortener.validate_variants(&weightless, &mut repo),
+            "weight",
+        );
+        let banned = [
+            variant("https://a.example/", 1),
+            variant("banned.example", 1),
+        ];
+        assert_input_error(
+            shortener.validate_variants(&banned, &mut repo),
+            "domain aren't allowed",
+        );
+    }
+
+    #[test]
+    fn test_delete_requires_matching_token() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let denied = shortener.delete_link("aardvarkable1", "wrong", &mut repo);
+        assert!(matches!(denied, Err(AppError::ForbiddenError)));
+        let allowed = shortener.delete_link("aardvarkable1", "secret", &mut repo);
+        assert!(allowed.is_ok());
+    }
+
+    #[test]
+    fn test_empty_edit_is_rejected() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let edit = LinkEdit {
+            link: None,
+            ttl: None,
+            redirect_type: None,
+        };
+        let result = shortener.edit_link("aardvarkable1", "secret", edit, &mut repo);
+        assert_input_error(result, "at least one of link, ttl or redirect_type");
+    }
+
+    fn bulk_link(link: &str, alias: Option<&str>) -> BulkLink {
+        BulkLink {
+            link: link.to_string(),
+            alias: alias.map(|a| a.to_string()),
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            redirect_type: RedirectType::Permanent,
+            interstitial: false,
+            max_visits: None,
+            forward_query: false,
+            forward_path: false,
+        }
+    }
+
+    #[test]
+    fn test_bulk_reports_errors_per_link() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let mut rng = rand::thread_rng();
+        let items = vec![
+            bulk_link("https://localhost:8080/", None),
+            bulk_link("https://localhost:8080/", Some("taken")),
+            bulk_link("https://localhost:8080/", Some("mine")),
+            bulk_link("https://localhost:8080/", Some("Mine")),
+            bulk_link("https://localhost:8080/", Some("no way!")),
+        ];
+        let results = shortener
+            .shorten_bulk(items, &ShortenOptions::default(), &mut repo, &mut rng)
+            .unwrap();
+        let errors: Vec<Option<&str>> = results
+            .iter()
+            .map(|r| match r {
+                BulkResult::Failed { error } => Some(error.as_str()),
+                BulkResult::Shortened(_) => None,
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                None,
+                Some("This alias is already taken. Please pick another one."),
+                None,
+                Some("This alias is already taken. Please pick another one."),
+                Some("A custom alias must be between 3 and 64 characters long and may only contain letters, digits, '-' and '_'."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_bulk_is_rejected() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let mut rng = rand::thread_rng();
+        let result =
+            shortener.shorten_bulk(Vec::new(), &ShortenOptions::default(), &mut repo, &mut rng);
+        assert_input_error(result, "between 1 and 500 links");
+    }
+
+    #[test]
+    fn test_taken_alias_is_rejected() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        assert_input_error(
+            shortener.validate_alias("Taken", &mut repo),
+            "already taken",
+        );
+        assert!(shortener.validate_alias("free", &mut repo).is_ok());
+    }
+
     #[test]
     fn test_generating_link_does_not_hang() {
         // This test is to ensure that the code does not hang when generating a link.
         // This is because there is an infinite loop in the code.
-        let mut repo = MockNamesRepository {};
-        let generator = NameGenerator::default();
-
-        let shortener = ShortenService::new("http://localhost:8080/", &generator);
+        let mut repo = MockNamesRepository::colliding(5);
+        let shortener = service();
         let mut rng = rand::thread_rng();
         let result = shortener.shorten_name(
             &mut "https://localhost:8080/".to_string(),
+            &ShortenOptions::default(),
             &mut repo,
             &mut rng,
         );
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_generating_link_gives_up_after_max_attempts() {
+        let mut repo = MockNamesRepository::colliding(MAX_NAME_ATTEMPTS);
+        let shortener = service();
+        let mut rng = rand::thread_rng();
+        let result = shortener.shorten_name(
+            &mut "https://localhost:8080/".to_string(),
+            &ShortenOptions::default(),
+            &mut repo,
+            &mut rng,
+        );
+        assert!(matches!(result, Err(AppError::InfraError(_))));
+    }
+
+    #[test]
+    fn test_alias_with_invalid_characters_is_rejected() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let result = shortener.validate_alias("my link!", &mut repo);
+        assert_input_error(result, "may only contain letters");
+    }
+
+    #[test]
+    fn test_denied_alias_is_rejected() {
+        let mut repo = MockNamesRepository::default();
+        let shortener = service();
+        let result = shortener.validate_alias("stupid-link", &mut repo);
+        assert_input_error(result, "isn't allowed");
+    }
+
+    #[test]
+    fn test_reserved_alias_is_rejected() {
+        let shortener = service();
+        assert_input_error(shortener.check_alias("Bulk"), "isn't allowed");
+        assert!(shortener.check_alias("bulky").is_ok());
+    }
 }
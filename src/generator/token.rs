@@ -0,0 +1,123 @@
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+const TOKEN_LENGTH: usize = 40;
+
+/// Makes a random secret that is handed out once and only stored as a hash.
+pub fn generate_token(rng: &mut rand::rngs::ThreadRng) -> String {
+    rng.sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Tokens are long random strings rather than user chosen passwords,
+/// so a plain SHA-256 digest is enough to store them safely.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Checks `token` against a hash made by `hash_token`. The comparison takes the same time
+/// wherever the hashes differ, so it doesn't tell how close a guess was.
+pub fn token_matches(token: &str, hash: &str) -> bool {
+    hash_token(token).as_bytes().ct_eq(hash.as_bytes()).into()
+}
+
+/// Hashes values that are easy to guess, like IP addresses, with a server secret. Without the
+/// secret the hash can't be reversed by trying every possible value.
+pub fn keyed_hash(secret: &str, value: &str) -> String {
+    let mut mac = keyed_mac(secret);
+    mac.update(value.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Makes a token that proves `value` was handed out by this server, until `expires_at`. The
+/// value itself isn't part of the token, whoever checks it has to know it already.
+pub fn sign_until(secret: &str, value: &str, expires_at: SystemTime) -> String {
+    let expiry = expires_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!(
+        "{expiry}.{}",
+        keyed_hash(secret, &format!("{expiry}:{value}"))
+    )
+}
+
+/// Checks a token made by `sign_until` for `value` that hasn't expired at `now`.
+pub fn verify_signed(secret: &str, value: &str, token: &str, now: SystemTime) -> bool {
+    let Some((expiry, signature)) = token.split_once('.') else {
+        return false;
+    };
+    let (Ok(expiry), Ok(signature)) = (expiry.parse::<u64>(), hex::decode(signature)) else {
+        return false;
+    };
+    let expires_at = UNIX_EPOCH.checked_add(Duration::from_secs(expiry));
+    if expires_at.map_or(true, |expires_at| expires_at <= now) {
+        return false;
+    }
+    let mut mac = keyed_mac(secret);
+    mac.update(format!("{expiry}:{value}").as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn keyed_mac(secret: &str) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_tokens_are_unique() {
+        let mut rng = rand::thread_rng();
+        let first = generate_token(&mut rng);
+        let second = generate_token(&mut rng);
+        assert_eq!(first.len(), TOKEN_LENGTH);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_hash_token_is_deterministic() {
+        assert_eq!(hash_token("secret"), hash_token("secret"));
+        assert_ne!(hash_token("secret"), hash_token("Secret"));
+    }
+
+    #[test]
+    fn test_token_matches_its_hash_only() {
+        let hash = hash_token("secret");
+        assert!(token_matches("secret", &hash));
+        assert!(!token_matches("Secret", &hash));
+        assert!(!token_matches("secret", &hash[1..]));
+    }
+
+    #[test]
+    fn test_keyed_hash_depends_on_the_secret() {
+        let hash = keyed_hash("secret", "ip:8.8.8.8");
+        assert_eq!(hash, keyed_hash("secret", "ip:8.8.8.8"));
+        assert_ne!(hash, keyed_hash("other", "ip:8.8.8.8"));
+        assert_ne!(hash, hash_token("ip:8.8.8.8"));
+    }
+
+    #[test]
+    fn test_signed_token_only_holds_for_its_value_until_it_expires() {
+        let now = SystemTime::now();
+        let token = sign_until("secret", "link:1", now + Duration::from_secs(60));
+        assert!(verify_signed("secret", "link:1", &token, now));
+        assert!(!verify_signed("secret", "link:2", &token, now));
+        assert!(!verify_signed("other", "link:1", &token, now));
+        assert!(!verify_signed(
+            "secret",
+            "link:1",
+            &token,
+            now + Duration::from_secs(61)
+        ));
+        let (_, signature) = token.split_once('.').unwrap();
+        let extended = format!("{}.{signature}", u64::MAX);
+        assert!(!verify_signed("secret", "link:1", &extended, now));
+    }
+}
@@ -0,0 +1,24 @@
+use std::net::IpAddr;
+
+use maxminddb::{geoip2, Reader};
+
+/// Looks up the country of IP addresses in a local MaxMind database, such as GeoLite2 Country
+/// or City. The whole database is read into memory when the app starts.
+pub struct GeoIp(Reader<Vec<u8>>);
+
+impl GeoIp {
+    /// A database that can't be read is logged and left out, rules on countries never match
+    /// without one.
+    pub fn open(path: &str) -> Option<Self> {
+        Reader::open_readfile(path)
+            .inspect_err(|e| log::warn!("The GeoIP database {path} couldn't be read: {e}"))
+            .ok()
+            .map(Self)
+    }
+
+    /// The ISO 3166-1 alpha-2 code of the country the address belongs to, if it's known.
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let country: geoip2::Country = self.0.lookup(ip).ok()?;
+        country.country?.iso_code.map(str::to_string)
+    }
+}
@@ -1,19 +1,27 @@
+mod auth;
 mod cleanup;
 pub mod config;
 pub mod errors;
 mod generator;
+mod geoip;
+mod pages;
+mod qr;
+mod rate_limit;
 mod routes;
 pub mod schema;
 use std::{future::IntoFuture, sync::Arc};
 
 use crate::{
-    cleanup::spawn_cleanup_task, config::AppConfig, generator::name_generator::NameGenerator,
+    auth::create_api_key, cleanup::spawn_cleanup_task, config::AppConfig,
+    generator::name_generator::NameGenerator,
 };
 use config::AppState;
 use generator::database::run_migration;
 use routes::make_router;
 use simplelog::*;
+use std::env;
 use std::fs::File;
+use std::net::SocketAddr;
 use std::panic;
 
 fn log_panic_hook() {
@@ -41,13 +49,33 @@ fn setup_logger() {
     .unwrap();
 }
 
+/// Handles the administrative commands that can be run instead of starting the server.
+fn run_command(command: &str, config: &AppConfig) {
+    let args: Vec<String> = env::args().skip(2).collect();
+    match (command, args.as_slice()) {
+        ("create-api-key", [name]) => create_api_key(&config.db_config, name, None, false),
+        ("create-api-key", [name, quota]) => {
+            let quota = quota.parse().expect("The daily quota must be a number.");
+            create_api_key(&config.db_config, name, Some(quota), false)
+        }
+        ("create-admin-key", [name]) => create_api_key(&config.db_config, name, None, true),
+        _ => eprintln!(
+            "Usage: squishlink_rs create-api-key <name> [daily quota]\n       squishlink_rs create-admin-key <name>"
+        ),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     setup_logger();
     log_panic_hook();
-    let name_generator = NameGenerator::default();
     let config = AppConfig::default();
     run_migration(&config.db_config);
+    if let Some(command) = env::args().nth(1) {
+        run_command(&command, &config);
+        return;
+    }
+    let name_generator = NameGenerator::with_deny_list(&config.deny_list_path);
     let addr = format!("0.0.0.0:{}", config.app_port);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     let app_state = Arc::new(AppState::new(config, name_generator));
@@ -55,6 +83,13 @@ async fn main() {
     // cleanup is an async function that awaits another async function.
     // If there was no input parameter we wouldn't have needed to do this.
     let cleanup = async move { spawn_cleanup_task(Arc::clone(&app_state)).await };
-    let (res1, _) = tokio::join!(axum::serve(listener, router).into_future(), cleanup);
+    let (res1, _) = tokio::join!(
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>()
+        )
+        .into_future(),
+        cleanup
+    );
     res1.unwrap();
 }
@@ -0,0 +1,137 @@
+use std::fs;
+
+use axum::http::{header::ACCEPT, HeaderMap};
+
+/// Pages are plain HTML files with `{{key}}` placeholders, see `render`.
+pub const LINK_INFO_TEMPLATE: &str = include_str!("../templates/link_info.html");
+/// Shown for links that don't exist (anymore). Can be replaced with `MISSING_LINK_TEMPLATE`.
+pub const MISSING_LINK_TEMPLATE: &str = include_str!("../templates/missing_link.html");
+/// Shown instead of redirecting when a link was disabled, for example after it was reported.
+pub const DISABLED_LINK_TEMPLATE: &str = include_str!("../templates/disabled_link.html");
+/// Shown instead of redirecting when visitors should see the destination first.
+pub const INTERSTITIAL_TEMPLATE: &str = include_str!("../templates/interstitial.html");
+/// Asks for the password of a protected link, the form posts it back to the short link.
+pub const PASSWORD_PROMPT_TEMPLATE: &str = include_str!("../templates/password_prompt.html");
+/// Swagger UI for the OpenAPI spec, the UI itself is loaded from a CDN.
+pub const API_DOCS_TEMPLATE: &str = include_str!("../templates/api_docs.html");
+/// The embedded web UI, it only talks to the JSON API and needs no other assets.
+pub const WEB_UI_TEMPLATE: &str = include_str!("../templates/web_ui.html");
+/// Filled into the `{{error}}` placeholder of the password prompt after a failed attempt.
+pub const PASSWORD_ERROR_FRAGMENT: &str = "<p class=\"error\">{{error}}</p>";
+/// Filled into the `{{fields}}` placeholder of the interstitial page, once per posted value.
+pub const HIDDEN_FIELD_FRAGMENT: &str =
+    "<input type=\"hidden\" name=\"{{name}}\" value=\"{{value}}\">";
+/// Filled into the `{{suggestion}}` placeholder of the missing link page.
+pub const SUGGESTION_FRAGMENT: &str =
+    "<p>Did you mean <a href=\"{{suggestion}}\">{{suggestion}}</a>?</p>";
+
+/// Reads a template from `path`, using the built-in one when no path is configured or the file
+/// can't be read.
+pub fn load_template(path: Option<&str>, built_in: &str) -> String {
+    let Some(path) = path else {
+        return built_in.to_string();
+    };
+    fs::read_to_string(path).unwrap_or_else(|e| {
+        log::warn!("The template {path} couldn't be read, using the built-in one instead: {e}");
+        built_in.to_string()
+    })
+}
+
+/// Fills in the placeholders of a template. Values are escaped, so they can't inject markup.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |page, (key, value)| {
+            page.replace(&format!("{{{{{key}}}}}"), &escape_html(value))
+        })
+}
+
+/// Fills in a placeholder with markup that is already safe, such as another rendered template.
+pub fn insert_html(template: &str, key: &str, html: &str) -> String {
+    template.replace(&format!("{{{{{key}}}}}"), html)
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Browsers ask for HTML, API clients that don't say anything get JSON.
+pub fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|header| header.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Command line clients like curl can ask for just the link instead of JSON.
+pub fn wants_plain_text(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|header| header.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain") && !accept.contains("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_escapes_values() {
+        let page = render(
+            "<a href=\"{{url}}\">{{url}}</a>",
+            &[("url", "https://x.com/?a=1&b=\"<script>")],
+        );
+        assert_eq!(
+            page,
+            "<a href=\"https://x.com/?a=1&amp;b=&quot;&lt;script&gt;\">https://x.com/?a=1&amp;b=&quot;&lt;script&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn test_insert_html_keeps_markup() {
+        let fragment = render(SUGGESTION_FRAGMENT, &[("suggestion", "https://x.com/a&b")]);
+        let page = insert_html("<body>{{suggestion}}</body>", "suggestion", &fragment);
+        assert_eq!(
+            page,
+            "<body><p>Did you mean <a href=\"https://x.com/a&amp;b\">https://x.com/a&amp;b</a>?</p></body>"
+        );
+    }
+
+    #[test]
+    fn test_load_template_falls_back_to_built_in() {
+        assert_eq!(load_template(None, "built-in"), "built-in");
+        let missing = load_template(Some("templates/does-not-exist.html"), "built-in");
+        assert_eq!(missing, "built-in");
+        let from_file = load_template(Some("templates/missing_link.html"), "built-in");
+        assert_eq!(from_file, MISSING_LINK_TEMPLATE);
+    }
+
+    #[test]
+    fn test_wants_html() {
+        let mut headers = HeaderMap::new();
+        assert!(!wants_html(&headers));
+        headers.insert(ACCEPT, "text/html,application/xhtml+xml".parse().unwrap());
+        assert!(wants_html(&headers));
+    }
+
+    #[test]
+    fn test_wants_plain_text() {
+        let mut headers = HeaderMap::new();
+        assert!(!wants_plain_text(&headers));
+        headers.insert(ACCEPT, "text/plain".parse().unwrap());
+        assert!(wants_plain_text(&headers));
+        headers.insert(ACCEPT, "application/json, text/plain".parse().unwrap());
+        assert!(!wants_plain_text(&headers));
+    }
+}
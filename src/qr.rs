@@ -0,0 +1,238 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::AppError;
+
+const DEFAULT_SIZE: u32 = 256;
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 2048;
+/// The QR specification asks for a quiet zone of four modules around the code.
+const DEFAULT_MARGIN: u32 = 4;
+const MAX_MARGIN: u32 = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+/// How much of the code may be damaged while staying readable, from 7% (L) to 30% (H).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+pub enum ErrorCorrection {
+    #[serde(alias = "l")]
+    L,
+    #[default]
+    #[serde(alias = "m")]
+    M,
+    #[serde(alias = "q")]
+    Q,
+    #[serde(alias = "h")]
+    H,
+}
+
+impl From<ErrorCorrection> for EcLevel {
+    fn from(level: ErrorCorrection) -> Self {
+        match level {
+            ErrorCorrection::L => EcLevel::L,
+            ErrorCorrection::M => EcLevel::M,
+            ErrorCorrection::Q => EcLevel::Q,
+            ErrorCorrection::H => EcLevel::H,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrOptions {
+    /// PNG unless SVG is asked for.
+    #[serde(default)]
+    pub format: QrFormat,
+    /// Width and height of the image in pixels, margin included. PNG images are rounded down
+    /// to a whole number of pixels per module.
+    pub size: Option<u32>,
+    /// The error correction level, M unless another one is asked for.
+    #[serde(default)]
+    pub ecc: ErrorCorrection,
+    /// Width of the empty border around the code, in modules.
+    pub margin: Option<u32>,
+}
+
+pub struct QrImage {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+/// The modules of a QR code, row by row, with the margin already added.
+struct QrMatrix {
+    width: usize,
+    dark: Vec<bool>,
+}
+
+impl QrMatrix {
+    fn new(data: &str, ecc: ErrorCorrection, margin: u32) -> Result<Self, AppError> {
+        let code = QrCode::with_error_correction_level(data, ecc.into())
+            .map_err(|e| AppError::InfraError(format!("Couldn't encode a QR code: {e}")))?;
+        let margin = margin as usize;
+        let code_width = code.width();
+        let width = code_width + 2 * margin;
+        let mut dark = vec![false; width * width];
+        for (i, color) in code.to_colors().into_iter().enumerate() {
+            let (x, y) = (i % code_width + margin, i / code_width + margin);
+            dark[y * width + x] = color == Color::Dark;
+        }
+        Ok(Self { width, dark })
+    }
+
+    fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+
+    fn to_svg(&self, size: u32) -> String {
+        let width = self.width;
+        let mut path = String::new();
+        for y in 0..width {
+            let mut x = 0;
+            while x < width {
+                if !self.is_dark(x, y) {
+                    x += 1;
+                    continue;
+                }
+                // Neighbouring dark modules are drawn as one rectangle to keep the file small.
+                let run = (x..width).take_while(|&x| self.is_dark(x, y)).count();
+                path.push_str(&format!("M{x} {y}h{run}v1h-{run}z"));
+                x += run;
+            }
+        }
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {width} {width}\" width=\"{size}\" height=\"{size}\" shape-rendering=\"crispEdges\"><rect width=\"100%\" height=\"100%\" fill=\"#fff\"/><path fill=\"#000\" d=\"{path}\"/></svg>"
+        )
+    }
+
+    fn to_png(&self, size: u32) -> Result<Vec<u8>, AppError> {
+        let scale = (size as usize / self.width).max(1);
+        let pixels = self.width * scale;
+        let mut image = Vec::with_capacity(pixels * pixels);
+        for y in 0..pixels {
+            for x in 0..pixels {
+                let dark = self.is_dark(x / scale, y / scale);
+                image.push(if dark { 0 } else { 255 });
+            }
+        }
+
+        let png_error = |e: png::EncodingError| AppError::InfraError(e.to_string());
+        let mut body = Vec::new();
+        let mut encoder = png::Encoder::new(&mut body, pixels as u32, pixels as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(png_error)?;
+        writer.write_image_data(&image).map_err(png_error)?;
+        writer.finish().map_err(png_error)?;
+        Ok(body)
+    }
+}
+
+pub fn render_qr(data: &str, options: &QrOptions) -> Result<QrImage, AppError> {
+    let size = options.size.unwrap_or(DEFAULT_SIZE);
+    if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+        let error_msg = format!("The size must be between {MIN_SIZE} and {MAX_SIZE} pixels.");
+        return Err(AppError::UserInputError(error_msg));
+    }
+    let margin = options.margin.unwrap_or(DEFAULT_MARGIN);
+    if margin > MAX_MARGIN {
+        let error_msg = format!("The margin can be at most {MAX_MARGIN} modules.");
+        return Err(AppError::UserInputError(error_msg));
+    }
+    let matrix = QrMatrix::new(data, options.ecc, margin)?;
+    match options.format {
+        QrFormat::Png => Ok(QrImage {
+            content_type: "image/png",
+            body: matrix.to_png(size)?,
+        }),
+        QrFormat::Svg => Ok(QrImage {
+            content_type: "image/svg+xml",
+            body: matrix.to_svg(size).into_bytes(),
+        }),
+    }
+}
+
+/// An SVG QR code with the default options, ready to be used as the `src` of an image.
+pub fn svg_data_uri(data: &str) -> Result<String, AppError> {
+    let options = QrOptions {
+        format: QrFormat::Svg,
+        ..Default::default()
+    };
+    let image = render_qr(data, &options)?;
+    Ok(format!(
+        "data:image/svg+xml;base64,{}",
+        STANDARD.encode(image.body)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK: &str = "https://localhost:8080/aardvarkable1";
+
+    #[test]
+    fn test_margin_surrounds_code() {
+        let matrix = QrMatrix::new(LINK, ErrorCorrection::M, 4).unwrap();
+        let code_width = QrCode::new(LINK).unwrap().width();
+        assert_eq!(matrix.width, code_width + 8);
+        assert!((0..matrix.width).all(|i| !matrix.is_dark(i, 0) && !matrix.is_dark(0, i)));
+        // Every code starts with a dark finder pattern in its top left corner.
+        assert!(matrix.is_dark(4, 4));
+    }
+
+    #[test]
+    fn test_png_is_scaled_to_fit_size() {
+        let options = QrOptions {
+            size: Some(300),
+            margin: Some(0),
+            ..Default::default()
+        };
+        let image = render_qr(LINK, &options).unwrap();
+        assert_eq!(image.content_type, "image/png");
+        let decoder = png::Decoder::new(image.body.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let code_width = QrCode::new(LINK).unwrap().width() as u32;
+        assert_eq!(reader.info().width, 300 / code_width * code_width);
+    }
+
+    #[test]
+    fn test_svg_has_requested_size() {
+        let options = QrOptions {
+            format: QrFormat::Svg,
+            size: Some(128),
+            ..Default::default()
+        };
+        let image = render_qr(LINK, &options).unwrap();
+        let svg = String::from_utf8(image.body).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("width=\"128\""));
+    }
+
+    #[test]
+    fn test_invalid_options_are_rejected() {
+        let too_small = QrOptions {
+            size: Some(10),
+            ..Default::default()
+        };
+        assert!(render_qr(LINK, &too_small).is_err());
+        let too_wide = QrOptions {
+            margin: Some(100),
+            ..Default::default()
+        };
+        assert!(render_qr(LINK, &too_wide).is_err());
+    }
+
+    #[test]
+    fn test_svg_data_uri() {
+        let uri = svg_data_uri(LINK).unwrap();
+        assert!(uri.starts_with("data:image/svg+xml;base64,"));
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+use crate::{auth::Caller, config::AppState, errors::AppError};
+
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+/// Buckets that are full again carry no information, they're dropped once this many exist.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+/// Dropping full buckets walks the whole map, so it happens at most this often.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+/// When this many buckets are in use, the least recently refilled ones make room for new clients.
+const MAX_BUCKETS: usize = 100_000;
+/// How many buckets are evicted at once, so the map isn't walked for every new client.
+const EVICTED_BUCKETS: usize = MAX_BUCKETS / 10;
+
+/// How many requests a single client may make. Requests refill at `per_minute` and up to
+/// `burst` of them can be made at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+struct Buckets {
+    by_client: HashMap<String, Bucket>,
+    last_sweep: Option<Instant>,
+}
+
+/// A token bucket rate limiter with one bucket per client.
+pub struct RateLimiter {
+    limit: Option<RateLimit>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// A limiter without a limit lets every request through.
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                last_sweep: None,
+            }),
+        }
+    }
+
+    /// Takes a token from the bucket of `client`. When the bucket is empty the time until the
+    /// next token becomes available is returned as the error.
+    pub fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        self.take(client, now, true)
+    }
+
+    /// Like `check`, but leaves the token in the bucket. Used to turn clients away before doing
+    /// work that only counts against them when it fails.
+    pub fn peek(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        self.take(client, now, false)
+    }
+
+    fn take(&self, client: &str, now: Instant, consume: bool) -> Result<(), Duration> {
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        let capacity = f64::from(limit.burst.max(1));
+        let per_second = f64::from(limit.per_minute) / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let Buckets {
+            by_client,
+            last_sweep,
+        } = &mut *buckets;
+        let sweep_due = last_sweep.map_or(true, |last| now.duration_since(last) >= SWEEP_INTERVAL);
+        if by_client.len() > MAX_IDLE_BUCKETS && sweep_due {
+            by_client.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens + elapsed * per_second < capacity
+            });
+            *last_sweep = Some(now);
+        }
+        if by_client.len() >= MAX_BUCKETS && !by_client.contains_key(client) {
+            // Turning new clients away here would let anyone with enough addresses lock everyone
+            // else out, so the clients that have been quiet the longest lose their bucket instead.
+            evict_least_recent(by_client, EVICTED_BUCKETS);
+        }
+        let bucket = by_client.entry(client.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            if consume {
+                bucket.tokens -= 1.0;
+            }
+            return Ok(());
+        }
+        if per_second == 0.0 {
+            return Err(Duration::from_secs(60));
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+    }
+}
+
+/// Drops the `count` buckets that were refilled the longest ago.
+fn evict_least_recent(by_client: &mut HashMap<String, Bucket>, count: usize) {
+    let mut refills = by_client
+        .values()
+        .map(|bucket| bucket.last_refill)
+        .collect::<Vec<Instant>>();
+    let Some(index) = count.checked_sub(1).filter(|index| *index < refills.len()) else {
+        by_client.clear();
+        return;
+    };
+    let (_, cutoff, _) = refills.select_nth_unstable(index);
+    let cutoff = *cutoff;
+    by_client.retain(|_, bucket| bucket.last_refill > cutoff);
+}
+
+/// Finds the address of the client. Requests coming from a trusted proxy are attributed to the
+/// right-most address in `X-Forwarded-For` that isn't a trusted proxy itself.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+    let forwarded = headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<IpAddr>>();
+
+    forwarded
+        .into_iter()
+        .rev()
+        .find(|ip| !is_trusted(ip))
+        .unwrap_or(peer)
+}
+
+/// Identifies the client behind a request: by its API key when it has one, by IP otherwise.
+pub fn client_identity(
+    caller: Option<&Caller>,
+    peer: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNet],
+) -> String {
+    if let Some(Caller(Some(api_key))) = caller {
+        return format!("key:{}", api_key.id);
+    }
+    match peer {
+        Some(addr) => format!("ip:{}", client_ip(addr.ip(), headers, trusted_proxies)),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// Like `client_identity`, but IPv6 clients are told apart by their /64 network. Providers hand
+/// out whole networks, so a single client can otherwise pick a new address for every request.
+pub fn network_identity(
+    caller: Option<&Caller>,
+    peer: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNet],
+) -> String {
+    if let Some(Caller(Some(api_key))) = caller {
+        return format!("key:{}", api_key.id);
+    }
+    match peer.map(|addr| client_ip(addr.ip(), headers, trusted_proxies)) {
+        Some(IpAddr::V6(ip)) => {
+            let network = Ipv6Addr::from(u128::from(ip) & (u128::MAX << 64));
+            format!("ip:{network}/64")
+        }
+        Some(ip) => format!("ip:{ip}"),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// The bucket of a request. Only keys that were looked up get a bucket of their own, anything
+/// else a client sends can be changed at will and is limited by network instead.
+fn client_key(state: &AppState, caller: Option<&Caller>, request: &Request) -> String {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    network_identity(
+        caller,
+        peer,
+        request.headers(),
+        &state.app_config.trusted_proxies,
+    )
+}
+
+/// Takes a token from the bucket of `key`, which doesn't have to identify a client. Password
+/// attempts for example are limited per link.
+pub fn limit_attempts(limiter: &RateLimiter, key: &str) -> Result<(), AppError> {
+    limiter
+        .check(key, Instant::now())
+        .map_err(too_many_requests)
+}
+
+/// Fails like `limit_attempts` once the bucket of `key` is empty, without taking a token.
+pub fn ensure_attempts_left(limiter: &RateLimiter, key: &str) -> Result<(), AppError> {
+    limiter.peek(key, Instant::now()).map_err(too_many_requests)
+}
+
+fn too_many_requests(retry_after: Duration) -> AppError {
+    // Retry-After only supports whole seconds, round up so the client doesn't retry too soon.
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    AppError::TooManyRequestsError(seconds)
+}
+
+/// Requests made with a valid API key share a bucket per key, other requests are limited per
+/// network. Goes inside the `authenticate` layer, which validated the key.
+pub async fn limit_shorten(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let caller = request.extensions().get::<Caller>();
+    let key = client_key(&state, caller, &request);
+    limit_attempts(&state.shorten_limiter, &key)?;
+    Ok(next.run(request).await)
+}
+
+/// Following links is limited per network whatever the request carries, as guessing names
+/// doesn't need a key.
+pub async fn limit_resolve(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    limit_attempts(&state.resolve_limiter, &client_key(&state, None, &request))?;
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::database::ApiKey;
+
+    #[test]
+    fn test_bucket_allows_burst_then_limits() {
+        let limiter = RateLimiter::new(Some(RateLimit {
+            per_minute: 60,
+            burst: 2,
+        }));
+        let now = Instant::now();
+        assert!(limiter.check("a", now).is_ok());
+        assert!(limiter.check("a", now).is_ok());
+        let retry_after = limiter.check("a", now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+        assert!(limiter.check("b", now).is_ok());
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(Some(RateLimit {
+            per_minute: 60,
+            burst: 1,
+        }));
+        let now = Instant::now();
+        assert!(limiter.check("a", now).is_ok());
+        assert!(limiter.check("a", now).is_err());
+        assert!(limiter.check("a", now + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_peek_leaves_the_token() {
+        let limiter = RateLimiter::new(Some(RateLimit {
+            per_minute: 60,
+            burst: 1,
+        }));
+        let now = Instant::now();
+        assert!(limiter.peek("a", now).is_ok());
+        assert!(limiter.check("a", now).is_ok());
+        assert!(limiter.peek("a", now).is_err());
+    }
+
+    #[test]
+    fn test_new_clients_get_through_when_the_map_is_full() {
+        let limiter = RateLimiter::new(Some(RateLimit {
+            per_minute: 1,
+            burst: 2,
+        }));
+        let start = Instant::now();
+        for client in 0..MAX_BUCKETS {
+            let now = start + Duration::from_millis(client as u64);
+            assert!(limiter.check(&client.to_string(), now).is_ok());
+        }
+        let now = start + Duration::from_millis(MAX_BUCKETS as u64);
+        assert!(limiter.check("new", now).is_ok());
+        // The clients seen last keep their bucket.
+        let last = (MAX_BUCKETS - 1).to_string();
+        assert!(limiter.check(&last, now).is_ok());
+        assert!(limiter.check(&last, now).is_err());
+    }
+
+    #[test]
+    fn test_no_limit_allows_everything() {
+        let limiter = RateLimiter::new(None);
+        let now = Instant::now();
+        assert!((0..1000).all(|_| limiter.check("a", now).is_ok()));
+    }
+
+    #[test]
+    fn test_client_ip_ignores_forwarded_for_from_untrusted_peer() {
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR_HEADER, "1.1.1.1".parse().unwrap());
+        let peer: IpAddr = "8.8.8.8".parse().unwrap();
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(client_ip(peer, &headers, &trusted), peer);
+    }
+
+    #[test]
+    fn test_client_ip_skips_trusted_proxies() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FORWARDED_FOR_HEADER,
+            "6.6.6.6, 1.1.1.1, 10.0.0.2".parse().unwrap(),
+        );
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let expected: IpAddr = "1.1.1.1".parse().unwrap();
+        assert_eq!(client_ip(peer, &headers, &trusted), expected);
+    }
+
+    #[test]
+    fn test_client_identity_prefers_api_key() {
+        let headers = HeaderMap::new();
+        let peer: SocketAddr = "8.8.8.8:1234".parse().unwrap();
+        let anonymous = Caller(None);
+        let identity = client_identity(Some(&anonymous), Some(peer), &headers, &[]);
+        assert_eq!(identity, "ip:8.8.8.8");
+        let api_key = ApiKey {
+            id: 7,
+            name: "test".to_string(),
+            is_admin: false,
+        };
+        let identity = client_identity(Some(&Caller(Some(api_key))), Some(peer), &headers, &[]);
+        assert_eq!(identity, "key:7");
+    }
+
+    #[test]
+    fn test_network_identity_groups_ipv6_networks() {
+        let headers = HeaderMap::new();
+        let first: SocketAddr = "[2001:db8:1:2:aaaa::1]:1234".parse().unwrap();
+        let second: SocketAddr = "[2001:db8:1:2:bbbb::2]:1234".parse().unwrap();
+        let identity = network_identity(None, Some(first), &headers, &[]);
+        assert_eq!(identity, "ip:2001:db8:1:2::/64");
+        assert_eq!(
+            identity,
+            network_identity(None, Some(second), &headers, &[])
+        );
+        let peer: SocketAddr = "8.8.8.8:1234".parse().unwrap();
+        assert_eq!(
+            network_identity(None, Some(peer), &headers, &[]),
+            "ip:8.8.8.8"
+        );
+    }
+}
@@ -1,59 +1,1083 @@
-use std::sync::Arc;
+/// The moderation API, only available to admin keys.
+mod admin;
+mod api_docs;
+mod input;
+/// The versioned API. The routes under `/s` are kept as they are for existing clients.
+mod v1;
+
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use axum::{
-    extract::{Path, State},
-    response::Redirect,
-    routing::{get, post},
-    Json, Router,
+    extract::{ConnectInfo, Path, Query, RawQuery, State},
+    http::{
+        header::{ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_TYPE, COOKIE, SET_COOKIE, USER_AGENT},
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{delete, get, patch, post},
+    Form, Json, Router,
 };
 use rand::thread_rng;
 use serde::Deserialize;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use url::Url;
+use utoipa::ToSchema;
 
 use crate::{
-    config::AppState,
+    auth::{authenticate, require_admin, Caller},
+    config::{AppState, CorsConfig},
     errors::AppError,
     generator::{
-        database::PostgresRepository,
-        shorten::{OutputLink, ShortenService, Shortener},
+        database::{
+            Link, LinkRule, LinkVariant, NamesRepository, PostgresRepository, RedirectType,
+            Tentative,
+        },
+        locale::preferred_languages,
+        moderation::{submit_report, ReportInput, Reporter},
+        name_generator::NameGenerator,
+        routing::{
+            minute_of_day, needs_country, pass_through, pick_destination, pick_variant, Platform,
+            RoutingRule, Variant, Visit,
+        },
+        shorten::{
+            BulkLink, BulkResult, LinkEdit, OwnedLink, ShortenOptions, ShortenService, Shortener,
+            Unlock,
+        },
+        token::keyed_hash,
+    },
+    pages::{
+        insert_html, render, wants_html, wants_plain_text, DISABLED_LINK_TEMPLATE,
+        HIDDEN_FIELD_FRAGMENT, INTERSTITIAL_TEMPLATE, LINK_INFO_TEMPLATE, PASSWORD_ERROR_FRAGMENT,
+        PASSWORD_PROMPT_TEMPLATE, SUGGESTION_FRAGMENT, WEB_UI_TEMPLATE,
+    },
+    qr::{render_qr, svg_data_uri, QrOptions},
+    rate_limit::{
+        client_ip, ensure_attempts_left, limit_attempts, limit_resolve, limit_shorten,
+        network_identity,
     },
 };
+use api_docs::{api_docs_page, openapi_spec};
+use input::ShortenInput;
 
 pub fn make_router(app_state: Arc<AppState>) -> Router {
     let state = Arc::clone(&app_state);
+    let shorten_limit = middleware::from_fn_with_state(Arc::clone(&state), limit_shorten);
+    let resolve_limit = middleware::from_fn_with_state(Arc::clone(&state), limit_resolve);
+    let authenticate = middleware::from_fn_with_state(Arc::clone(&state), authenticate);
+    let admin = Router::new()
+        .route("/admin/links", get(admin::search_links))
+        .route("/admin/links/:code", delete(admin::delete_link))
+        .route("/admin/links/:code/disable", post(admin::disable_link))
+        .route("/admin/links/:code/enable", post(admin::enable_link))
+        .route(
+            "/admin/banned-domains",
+            get(admin::banned_domains).post(admin::ban_domain),
+        )
+        .route("/admin/banned-domains/:domain", delete(admin::unban_domain))
+        .route("/admin/reports", get(admin::open_reports))
+        .route(
+            "/admin/links/:code/reports/resolve",
+            post(admin::resolve_reports),
+        )
+        .route("/admin/audit-log", get(admin::audit_log))
+        .route_layer(middleware::from_fn(require_admin));
+    // Every API route is behind `authenticate`, handlers that don't look at the caller still
+    // reject unknown keys.
+    let mut api = Router::new()
+        .route(
+            "/s",
+            post(shorten).layer(shorten_limit.clone()).get(list_links),
+        )
+        .route("/s/bulk", post(shorten_bulk).layer(shorten_limit.clone()))
+        .route("/s/:short_link", patch(edit_link).delete(delete_link))
+        .route(
+            "/s/:short_link/info",
+            get(link_info).layer(resolve_limit.clone()),
+        )
+        .route(
+            "/s/:short_link/qr",
+            get(link_qr).layer(resolve_limit.clone()),
+        )
+        .route(
+            "/s/:short_link/report",
+            post(report_link).layer(shorten_limit.clone()),
+        )
+        .route(
+            "/api/v1/links",
+            post(v1::create_link)
+                .layer(shorten_limit)
+                .get(v1::list_links),
+        )
+        .route(
+            "/api/v1/links/:code",
+            get(v1::get_link)
+                .layer(resolve_limit.clone())
+                .delete(v1::delete_link),
+        )
+        .merge(admin)
+        .route_layer(authenticate)
+        .route("/openapi.json", get(openapi_spec));
+    if state.app_config.api_docs_ui {
+        api = api.route("/docs", get(api_docs_page));
+    }
+    if state.app_config.web_ui {
+        api = api.route("/", get(web_ui_page));
+    }
+    if let Some(cors) = cors_layer(&state.app_config.cors) {
+        api = api.layer(cors);
+    }
+    // Redirects are followed by browsers navigating to them, they don't need CORS headers.
+    // `/s/:short_link` is shared with the API, merging the API in last keeps its CORS layer
+    // around the fallback that answers preflight requests.
+    // Only links that forward paths can be followed with one. The static routes above win, so
+    // `pass_through` refuses paths starting with their segments as well.
     Router::new()
-        .route("/s", post(shorten))
-        .route("/s/:short_link", get(retrieve_original_link))
+        .route(
+            "/s/:short_link",
+            get(retrieve_original_link)
+                .post(unlock_link)
+                .layer(resolve_limit.clone()),
+        )
+        .route(
+            "/s/:short_link/*rest",
+            get(retrieve_original_link)
+                .post(unlock_link)
+                .layer(resolve_limit),
+        )
+        .merge(api)
         .with_state(state)
 }
 
-#[derive(Deserialize)]
+fn cors_layer(config: &CorsConfig) -> Option<CorsLayer> {
+    if !config.is_enabled() {
+        return None;
+    }
+    let allowed_origins = if config.allow_any_origin {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.allowed_origins.clone())
+    };
+    let layer = CorsLayer::new()
+        .allow_origin(allowed_origins)
+        .allow_methods(config.allowed_methods.clone())
+        .allow_headers(config.allowed_headers.clone())
+        .max_age(config.max_age)
+        .allow_credentials(config.allow_credentials);
+    Some(layer)
+}
+
+async fn web_ui_page() -> Html<&'static str> {
+    Html(WEB_UI_TEMPLATE)
+}
+
+#[derive(Default, Deserialize, ToSchema)]
 struct InputLink {
+    /// The link to shorten. Links without a scheme are assumed to be HTTPS.
+    #[serde(alias = "url")]
     link: String,
+    /// A custom name for the link instead of a generated one.
+    alias: Option<String>,
+    /// The language to generate the name in, `Accept-Language` is used when it's missing.
+    locale: Option<String>,
+    /// Lifetime of the link in seconds, counted from `not_before` when it's given.
+    ttl: Option<u64>,
+    /// RFC 3339 timestamp of when the link starts working, right away when it's missing.
+    not_before: Option<String>,
+    /// RFC 3339 timestamp of when the link stops working, instead of a `ttl`.
+    not_after: Option<String>,
+    #[serde(default)]
+    redirect_type: RedirectType,
+    /// Adds a QR code of the short link to the response.
+    #[serde(default, deserialize_with = "input::checkbox")]
+    qr: bool,
+    /// Shows visitors the destination with a button to continue instead of redirecting them.
+    #[serde(default, deserialize_with = "input::checkbox")]
+    interstitial: bool,
+    /// Visitors have to enter this password before they're sent on.
+    password: Option<String>,
+    /// The link stops working after it was followed this often, 1 makes a one-time link.
+    max_visits: Option<u32>,
+    /// Send visitors that match one of them somewhere else, the first match wins. Visitors that
+    /// match none go to `link`.
+    #[serde(default)]
+    rules: Vec<RoutingRule>,
+    /// Split the visitors that match no rule between these destinations instead of sending them
+    /// to `link`, for A/B tests.
+    #[serde(default)]
+    variants: Vec<Variant>,
+    /// Send returning visitors to the variant they got before, with a cookie.
+    #[serde(default, deserialize_with = "input::checkbox")]
+    sticky_variants: bool,
+    /// Pass the query string of a visit on to the destination. Parameters the destination has
+    /// itself are kept.
+    #[serde(default, deserialize_with = "input::checkbox")]
+    forward_query: bool,
+    /// Let visitors add a path after the name, which is appended to the destination.
+    #[serde(default, deserialize_with = "input::checkbox")]
+    forward_path: bool,
 }
 
+impl InputLink {
+    fn options(&self, headers: &HeaderMap, api_key_id: Option<i32>) -> ShortenOptions<'_> {
+        ShortenOptions {
+            alias: self.alias.as_deref(),
+            locale: self.locale.as_deref(),
+            accepted_languages: accepted_languages(headers),
+            ttl: self.ttl,
+            redirect_type: self.redirect_type,
+            api_key_id,
+            interstitial: self.interstitial,
+            password: self.password.as_deref(),
+            max_visits: self.max_visits,
+            rules: &self.rules,
+            variants: &self.variants,
+            sticky_variants: self.sticky_variants,
+            forward_query: self.forward_query,
+            forward_path: self.forward_path,
+            not_before: self.not_before.as_deref(),
+            not_after: self.not_after.as_deref(),
+        }
+    }
+}
+
+const MANAGEMENT_TOKEN_HEADER: &str = "x-management-token";
+/// Followed by the name of the link, holds the variant a visitor got.
+const VARIANT_COOKIE_PREFIX: &str = "variant_";
+/// Browsers may remember permanent redirects for this long at most, so edits still reach them.
+const PERMANENT_REDIRECT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn make_service(state: &AppState) -> ShortenService<'_, '_, NameGenerator> {
+    let config = &state.app_config;
+    ShortenService::new(&config.base_url, &state.name_generator)
+        .with_ttl(config.default_ttl, config.max_ttl)
+        .with_pass_secret(&config.pass_secret)
+}
+
+fn accepted_languages(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|header| header.to_str().ok())
+        .map(preferred_languages)
+        .unwrap_or_default()
+}
+
+fn management_token(headers: &HeaderMap) -> Result<&str, AppError> {
+    optional_management_token(headers).ok_or(AppError::UnauthorizedError)
+}
+
+/// The management token for routes that work without one too, but show its holder more.
+fn optional_management_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(MANAGEMENT_TOKEN_HEADER)
+        .and_then(|header| header.to_str().ok())
+}
+
+/// Shortens a link, generating a name for it unless an alias is given.
+#[utoipa::path(
+    post,
+    path = "/s",
+    tag = "links",
+    request_body(
+        content = InputLink,
+        description = "The link and options as JSON or as an `application/x-www-form-urlencoded` form. A `text/plain` body contains just the link.",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "The shortened link. Clients that accept `text/plain` but not JSON get just the link, with the management token in the `X-Management-Token` header.", content(
+            ("application/json" = OutputLink),
+            ("text/plain" = String),
+        )),
+        (status = 400, description = "The link or one of the options is invalid", body = String),
+        (status = 415, description = "The body isn't JSON, a form or plain text", body = String),
+        (status = 401, description = "An API key is required or the supplied key is invalid"),
+        (status = 429, description = "The rate limit or the daily quota of the API key was exceeded"),
+    ),
+    security((), ("api_key" = []))
+)]
 async fn shorten(
     state: State<Arc<AppState>>,
-    mut input: Json<InputLink>,
-) -> Result<Json<OutputLink>, AppError> {
-    let service = ShortenService::new(&state.app_config.base_url, &state.name_generator);
+    caller: Caller,
+    headers: HeaderMap,
+    ShortenInput(input): ShortenInput,
+) -> Result<Response, AppError> {
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let require_api_key = state.app_config.require_api_key;
+    let api_key_id = caller.authorize_shorten(require_api_key)?;
+    let options = input.options(&headers, api_key_id);
+    let mut link = input.link.clone();
+    let service = make_service(&state);
     let mut rng = thread_rng();
+    let mut shortened = service.shorten_name(&mut link, &options, &mut names_repo, &mut rng)?;
+    if input.qr {
+        let qr = svg_data_uri(&shortened.to_string())?;
+        shortened = shortened.with_qr(qr);
+    }
+    if wants_plain_text(&headers) {
+        let token = shortened.management_token().unwrap_or_default().to_string();
+        let headers = [(MANAGEMENT_TOKEN_HEADER, token)];
+        return Ok((headers, format!("{shortened}\n")).into_response());
+    }
+    Ok(Json(shortened).into_response())
+}
+
+/// Shortens up to 500 links at once. Links that can't be shortened get an error of their own.
+/// The rate limit only takes one token per request, so bulk requests always need an API key and
+/// count against its daily quota link by link.
+#[utoipa::path(
+    post,
+    path = "/s/bulk",
+    tag = "links",
+    request_body = Vec<BulkLink>,
+    responses(
+        (status = 200, description = "One result per link, in the order of the request", body = Vec<BulkResult>),
+        (status = 400, description = "The request is empty or contains too many links", body = String),
+        (status = 401, description = "No API key was supplied or the supplied key is invalid"),
+        (status = 429, description = "The rate limit or the daily quota of the API key was exceeded"),
+    ),
+    security(("api_key" = []))
+)]
+async fn shorten_bulk(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    headers: HeaderMap,
+    Json(items): Json<Vec<BulkLink>>,
+) -> Result<Json<Vec<BulkResult>>, AppError> {
+    let api_key = caller.require_key()?;
     let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
-    let shortened = service.shorten_name(&mut input.link, &mut names_repo, &mut rng)?;
-    Ok(Json(shortened))
+    let options = ShortenOptions {
+        accepted_languages: accepted_languages(&headers),
+        api_key_id: Some(api_key.id),
+        ..Default::default()
+    };
+    let service = make_service(&state);
+    let mut rng = thread_rng();
+    let results = service.shorten_bulk(items, &options, &mut names_repo, &mut rng)?;
+    Ok(Json(results))
 }
 
-async fn retrieve_original_link(
+/// Lists the links created with the caller's API key.
+#[utoipa::path(
+    get,
+    path = "/s",
+    tag = "links",
+    responses(
+        (status = 200, description = "The links of the API key, newest first", body = Vec<OwnedLink>),
+        (status = 401, description = "No valid API key was supplied"),
+    ),
+    security(("api_key" = []))
+)]
+async fn list_links(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<OwnedLink>>, AppError> {
+    let api_key = caller.require_key()?;
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let owned = service.links_for_key(api_key, &mut names_repo)?;
+    Ok(Json(owned))
+}
+
+/// Shows where a link goes without following it, as JSON or as a preview page for browsers.
+///
+/// Appending a `+` to a short link, as in `/s/Name123+`, shows the same information.
+#[utoipa::path(
+    get,
+    path = "/s/{short_link}/info",
+    tag = "links",
+    params(("short_link" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 200, description = "Information about the link", content(
+            ("application/json" = LinkInfo),
+            ("text/html" = String),
+        )),
+        (status = 401, description = "The link is protected by a password, its destination stays hidden", body = String),
+        (status = 403, description = "The link isn't active yet", body = String),
+        (status = 404, description = "The link doesn't exist", body = String),
+        (status = 410, description = "The link expired or was deleted", body = String),
+    )
+)]
+async fn link_info(
     state: State<Arc<AppState>>,
     short_link: Path<String>,
-) -> Result<Redirect, AppError> {
-    let service = ShortenService::new(&state.app_config.base_url, &state.name_generator);
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    info_response(&state, &short_link, &headers)
+}
+
+/// Renders a QR code of the full short link, for printing it on posters and the like. Links
+/// that aren't active yet or are protected get one as well, only deleted links don't.
+#[utoipa::path(
+    get,
+    path = "/s/{short_link}/qr",
+    tag = "links",
+    params(
+        ("short_link" = String, Path, description = "The name of the short link"),
+        QrOptions,
+    ),
+    responses(
+        (status = 200, description = "The QR code", content(
+            ("image/png" = [u8]),
+            ("image/svg+xml" = String),
+        )),
+        (status = 400, description = "One of the options is invalid", body = String),
+        (status = 404, description = "The link doesn't exist", body = String),
+        (status = 410, description = "The link was deleted", body = String),
+    )
+)]
+async fn link_qr(
+    state: State<Arc<AppState>>,
+    short_link: Path<String>,
+    Query(options): Query<QrOptions>,
+) -> Result<Response, AppError> {
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let link = service.qr_link(&short_link, &mut names_repo)?;
+    let image = render_qr(&link, &options)?;
+    Ok(([(CONTENT_TYPE, image.content_type)], image.body).into_response())
+}
+
+/// Reports a link as harmful. Links reported by enough different clients are disabled until a
+/// moderator looks at them.
+#[utoipa::path(
+    post,
+    path = "/s/{short_link}/report",
+    tag = "links",
+    params(("short_link" = String, Path, description = "The name of the short link")),
+    request_body = ReportInput,
+    responses(
+        (status = 202, description = "The report was received"),
+        (status = 400, description = "The report is invalid", body = String),
+        (status = 404, description = "The link doesn't exist", body = String),
+        (status = 429, description = "Too many requests were made"),
+    )
+)]
+async fn report_link(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    short_link: Path<String>,
+    headers: HeaderMap,
+    Json(input): Json<ReportInput>,
+) -> Result<StatusCode, AppError> {
+    let peer = connect_info.map(|ConnectInfo(addr)| addr);
+    let trusted = &state.app_config.trusted_proxies;
+    let identity = network_identity(Some(&caller), peer, &headers, trusted);
+    let reporter = Reporter {
+        identity_hash: keyed_hash(&state.app_config.reporter_secret, &identity),
+        user_agent: headers
+            .get(USER_AGENT)
+            .and_then(|agent| agent.to_str().ok()),
+        api_key_id: caller.0.as_ref().map(|api_key| api_key.id),
+    };
+    let threshold = state.app_config.report_disable_threshold;
+    let mut repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    submit_report(&short_link, input, &reporter, threshold, &mut repo)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+fn info_response(
+    state: &AppState,
+    short_link: &str,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let service = make_service(state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let info = service.link_info(short_link, &mut names_repo)?;
+    if wants_html(headers) {
+        let visits = info.visits.to_string();
+        let page = render(
+            LINK_INFO_TEMPLATE,
+            &[
+                ("link", &info.link),
+                ("original_link", &info.original_link),
+                ("created_at", &info.created_at),
+                ("expires_at", &info.expires_at),
+                ("visits", &visits),
+            ],
+        );
+        return Ok(Html(page).into_response());
+    }
+    Ok(Json(info).into_response())
+}
+
+/// The name of the link being followed, and the path after it for links that forward paths.
+#[derive(Deserialize)]
+struct LinkPath {
+    short_link: String,
+    #[serde(default)]
+    rest: Option<String>,
+}
+
+/// What the destination of a visit may depend on.
+struct Visitor<'a> {
+    headers: &'a HeaderMap,
+    peer: Option<SocketAddr>,
+    path: Option<&'a str>,
+    query: Option<&'a str>,
+    /// Whether the visit is counted. `HEAD` requests, e.g. from link previews, only look.
+    counts: bool,
+    /// What the visitor posted, visits with a form are sent on with a 303.
+    form: Option<&'a FollowForm>,
+}
+
+/// Follows a short link. Links that don't exist or aren't valid anymore show an error page, or
+/// redirect to the configured fallback page. Links with routing rules always redirect
+/// temporarily, as the destination depends on the visitor. Links that forward paths can be
+/// followed as `/s/{short_link}/{path}` as well.
+#[utoipa::path(
+    get,
+    path = "/s/{short_link}",
+    tag = "links",
+    params(("short_link" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 200, description = "A page showing the destination with a button to continue, for links behind an interstitial. The visit is counted once the visitor continues", body = String, content_type = "text/html"),
+        (status = 307, description = "Redirect to a link with a temporary redirect type, or to the fallback page"),
+        (status = 308, description = "Redirect to a link with a permanent redirect type, browsers may cache it for up to a day"),
+        (status = 401, description = "The link is protected by a password, a page asks for it", body = String, content_type = "text/html"),
+        (status = 403, description = "The link isn't active yet", body = String, content_type = "text/html"),
+        (status = 404, description = "The link doesn't exist", body = String, content_type = "text/html"),
+        (status = 410, description = "The link expired, was deleted, was disabled or was used up", body = String, content_type = "text/html"),
+    )
+)]
+async fn retrieve_original_link(
+    state: State<Arc<AppState>>,
+    method: Method,
+    Path(LinkPath { short_link, rest }): Path<LinkPath>,
+    RawQuery(query): RawQuery,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    // `/s/Name123+` is a shorthand for `/s/Name123/info`.
+    if let Some(short_link) = short_link.strip_suffix('+').filter(|_| rest.is_none()) {
+        return info_response(&state, short_link, &headers);
+    }
+    let service = make_service(&state);
     let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
-    let original_maybe = service.get_original_name(&short_link, &mut names_repo);
-    if let Ok(original) = original_maybe {
-        Ok(Redirect::permanent(&original))
+    let visitor = Visitor {
+        headers: &headers,
+        peer: peer.map(|ConnectInfo(addr)| addr),
+        path: rest.as_deref(),
+        query: query.as_deref(),
+        counts: method != Method::HEAD,
+        form: None,
+    };
+    let result = follow_link(&state, &service, &short_link, visitor, &mut names_repo);
+    match result {
+        Ok(response) => Ok(response),
+        Err(AppError::PasswordRequiredError) => {
+            Ok(password_prompt(&short_link, StatusCode::UNAUTHORIZED, None))
+        }
+        Err(error) => unavailable_link(&state, &service, &short_link, error, &mut names_repo),
+    }
+}
+
+/// Posted by the password prompt and by the continue button of the interstitial page.
+#[derive(Deserialize, ToSchema)]
+struct FollowForm {
+    /// The password of a protected link.
+    #[serde(default)]
+    password: Option<String>,
+    /// Handed out by the interstitial page of a protected link in place of its password.
+    #[serde(default)]
+    pass: Option<String>,
+    /// The visitor saw the destination on the interstitial page and wants to go there.
+    #[serde(default)]
+    confirmed: bool,
+    /// The variant of a split link that the interstitial page showed.
+    #[serde(default)]
+    variant: Option<i32>,
+}
+
+/// Submits the password of a protected link, or continues from the interstitial page. The right
+/// password redirects to the destination, a wrong one shows the prompt again. Wrong passwords
+/// are limited per link and client, and with a higher limit per link.
+#[utoipa::path(
+    post,
+    path = "/s/{short_link}",
+    tag = "links",
+    params(("short_link" = String, Path, description = "The name of the short link")),
+    request_body(content = FollowForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "A page showing the destination with a button to continue, for links behind an interstitial that wasn't confirmed yet", body = String, content_type = "text/html"),
+        (status = 303, description = "The password is right or the visit was confirmed, redirect to the link"),
+        (status = 401, description = "The link is protected by a password and none was given", body = String, content_type = "text/html"),
+        (status = 403, description = "The password is wrong and the page asks for it again, or the link isn't active yet", body = String, content_type = "text/html"),
+        (status = 404, description = "The link doesn't exist", body = String, content_type = "text/html"),
+        (status = 410, description = "The link expired, was deleted, was disabled or was used up", body = String, content_type = "text/html"),
+        (status = 429, description = "The password of this link was wrong too often", body = String),
+    )
+)]
+async fn unlock_link(
+    state: State<Arc<AppState>>,
+    Path(LinkPath { short_link, rest }): Path<LinkPath>,
+    RawQuery(query): RawQuery,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Form(form): Form<FollowForm>,
+) -> Result<Response, AppError> {
+    let peer = peer.map(|ConnectInfo(addr)| addr);
+    let attempts = form.password.as_ref().map(|_| {
+        let trusted = &state.app_config.trusted_proxies;
+        PasswordAttempts::new(&short_link, network_identity(None, peer, &headers, trusted))
+    });
+    if let Some(attempts) = &attempts {
+        attempts.ensure_left(&state)?;
+    }
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let visitor = Visitor {
+        headers: &headers,
+        peer,
+        path: rest.as_deref(),
+        query: query.as_deref(),
+        counts: true,
+        form: Some(&form),
+    };
+    let result = follow_link(&state, &service, &short_link, visitor, &mut names_repo);
+    match result {
+        Ok(response) => Ok(response),
+        Err(AppError::PasswordRequiredError) => {
+            Ok(password_prompt(&short_link, StatusCode::UNAUTHORIZED, None))
+        }
+        Err(AppError::WrongPasswordError) => {
+            if let Some(attempts) = &attempts {
+                attempts.count_wrong(&state)?;
+            }
+            Ok(password_prompt(
+                &short_link,
+                StatusCode::FORBIDDEN,
+                Some("The password is wrong. Please try again."),
+            ))
+        }
+        Err(error) => unavailable_link(&state, &service, &short_link, error, &mut names_repo),
+    }
+}
+
+/// The buckets a wrong password is taken from. Right passwords don't count, so visitors that
+/// know the password can't be locked out by those who don't.
+struct PasswordAttempts {
+    link: String,
+    client: String,
+}
+
+impl PasswordAttempts {
+    fn new(short_link: &str, client: String) -> Self {
+        let link = short_link.to_lowercase();
+        let client = format!("{link}:{client}");
+        Self { link, client }
+    }
+
+    fn ensure_left(&self, state: &AppState) -> Result<(), AppError> {
+        ensure_attempts_left(&state.password_limiter, &self.client)?;
+        ensure_attempts_left(&state.password_link_limiter, &self.link)
+    }
+
+    fn count_wrong(&self, state: &AppState) -> Result<(), AppError> {
+        limit_attempts(&state.password_limiter, &self.client)?;
+        limit_attempts(&state.password_link_limiter, &self.link)
+    }
+}
+
+/// Sends the visitor on to the destination, or shows them the interstitial page first. The
+/// routing rules or variants of the link may pick another destination than its own, and the
+/// path and query of the visit are added to it when the link forwards them. Browsers repeat the
+/// request for 307 and 308 redirects, so visits with a form are sent on with a 303 instead.
+///
+/// The query that resolves the link counts the visit as well. It's taken back when the visitor
+/// isn't sent on after all: for `HEAD` requests, the interstitial page and visits that lead
+/// nowhere, so those don't use up a limited link.
+fn follow_link(
+    state: &AppState,
+    service: &ShortenService<'_, '_, NameGenerator>,
+    short_link: &str,
+    visitor: Visitor,
+    names_repo: &mut PostgresRepository,
+) -> Result<Response, AppError> {
+    let Visitor {
+        headers,
+        peer,
+        path,
+        query,
+        counts,
+        form,
+    } = visitor;
+    let unlock = form.and_then(|form| match (&form.password, &form.pass) {
+        (Some(password), _) => Some(Unlock::Password(password)),
+        (None, Some(pass)) => Some(Unlock::Pass(pass)),
+        (None, None) => None,
+    });
+    names_repo.tentatively(|names_repo| {
+        let original = service.get_original_name(short_link, unlock, names_repo)?;
+        let rules = names_repo.routing_rules(&original)?;
+        let visit = describe_visit(state, &rules, headers, peer);
+        let rule_destination = pick_destination(&rules, &visit);
+        let shown_variant = form.and_then(|form| form.variant);
+        let variant = match rule_destination {
+            Some(_) => None,
+            None => choose_variant(&original, headers, shown_variant, names_repo)?,
+        };
+        let destination = rule_destination
+            .or(variant.as_ref().map(|variant| variant.destination.as_str()))
+            .unwrap_or(&original.original_link);
+        if path.is_some() && !original.forward_path {
+            return Err(AppError::UnknownPathError);
+        }
+        let query = query.filter(|_| original.forward_query);
+        let destination = pass_through(destination, path, query)?;
+        let destination = destination.as_str();
+
+        let interstitial = &state.app_config.interstitial;
+        let confirmed = form.is_some_and(|form| form.confirmed);
+        if !confirmed && interstitial.applies_to(destination, original.interstitial) {
+            let pass = original
+                .password_hash
+                .as_ref()
+                .and_then(|_| service.issue_pass(&original));
+            let page =
+                interstitial_page(short_link, destination, pass.as_deref(), variant.as_ref());
+            return Ok(Tentative::Discard(page));
+        }
+        if let Some(variant) = &variant {
+            names_repo.record_variant_visit(&original, variant.position)?;
+        }
+        let varies = !rules.is_empty() || variant.is_some();
+        let mut response = match form {
+            Some(_) => Redirect::to(destination).into_response(),
+            None if varies => Redirect::temporary(destination).into_response(),
+            None => match RedirectType::from_db(&original.redirect_type) {
+                RedirectType::Permanent => permanent_redirect(&original, destination),
+                RedirectType::Temporary => Redirect::temporary(destination).into_response(),
+            },
+        };
+        let cookie = variant
+            .filter(|_| original.sticky_variants)
+            .and_then(|variant| {
+                variant_cookie(&state.app_config.base_url, short_link, &original, &variant)
+            });
+        if let Some(cookie) = cookie {
+            response.headers_mut().insert(SET_COOKIE, cookie);
+        }
+        Ok(match counts {
+            true => Tentative::Keep(response),
+            false => Tentative::Discard(response),
+        })
+    })
+}
+
+/// Shows where a link leads. Its continue button posts back what following the link takes: a
+/// pass for a protected link, and the variant that was shown so the visitor ends up there.
+fn interstitial_page(
+    short_link: &str,
+    destination: &str,
+    pass: Option<&str>,
+    variant: Option<&LinkVariant>,
+) -> Response {
+    let variant = variant.map(|variant| variant.position.to_string());
+    let fields: String = [
+        ("confirmed", Some("true")),
+        ("pass", pass),
+        ("variant", variant.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        Some(render(
+            HIDDEN_FIELD_FRAGMENT,
+            &[("name", name), ("value", value?)],
+        ))
+    })
+    .collect();
+    let page = render(
+        INTERSTITIAL_TEMPLATE,
+        &[("link", short_link), ("destination", destination)],
+    );
+    let page = insert_html(&page, "fields", &fields);
+    // The page may carry a pass for a protected link.
+    ([(CACHE_CONTROL, "no-store")], Html(page)).into_response()
+}
+
+/// A 308 that private caches may keep until the link expires, but no longer than a day. Shared
+/// caches must not keep it, the link may be limited to a number of visits or protected.
+fn permanent_redirect(original: &Link, destination: &str) -> Response {
+    let until_expiry = original
+        .expires_at
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    let max_age = until_expiry.min(PERMANENT_REDIRECT_MAX_AGE).as_secs();
+    let mut response = Redirect::permanent(destination).into_response();
+    let cache_control = format!("private, max-age={max_age}");
+    if let Ok(value) = HeaderValue::from_str(&cache_control) {
+        response.headers_mut().insert(CACHE_CONTROL, value);
+    }
+    response
+}
+
+/// Picks one of the variants of a split link for this visit. Visitors that continue from the
+/// interstitial page get the variant it showed, returning visitors of sticky links the one they
+/// got before.
+fn choose_variant(
+    original: &Link,
+    headers: &HeaderMap,
+    shown: Option<i32>,
+    names_repo: &mut PostgresRepository,
+) -> Result<Option<LinkVariant>, AppError> {
+    let variants = names_repo.variants(original)?;
+    let previous = match original.sticky_variants {
+        true => previous_variant(headers, &original.short_link),
+        false => None,
+    };
+    let previous = shown.or(previous);
+    let Some(variant) = pick_variant(&variants, previous, &mut thread_rng()).cloned() else {
+        return Ok(None);
+    };
+    Ok(Some(variant))
+}
+
+/// The variant a returning visitor got before, as remembered by their cookie.
+fn previous_variant(headers: &HeaderMap, short_link: &str) -> Option<i32> {
+    let cookie_name = format!("{VARIANT_COOKIE_PREFIX}{short_link}");
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == cookie_name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// Remembers the variant until the link expires. The cookie is only sent along to the link
+/// itself, `short_link` is the name as it was visited since paths are case-sensitive.
+fn variant_cookie(
+    base_url: &str,
+    short_link: &str,
+    original: &Link,
+    variant: &LinkVariant,
+) -> Option<HeaderValue> {
+    let max_age = original
+        .expires_at
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+        .as_secs();
+    let links_path = Url::parse(base_url).map_or("/".to_string(), |url| url.path().to_string());
+    let cookie = format!(
+        "{VARIANT_COOKIE_PREFIX}{}={}; Max-Age={max_age}; Path={links_path}{short_link}; HttpOnly; SameSite=Lax",
+        original.short_link, variant.position
+    );
+    // Stored names are alphanumeric, but the visited name only has to match after lowercasing.
+    HeaderValue::from_str(&cookie).ok()
+}
+
+/// Collects what routing rules can match on. The country is only looked up when a rule needs it.
+fn describe_visit(
+    state: &AppState,
+    rules: &[LinkRule],
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Visit {
+    let country = match (&state.geoip, peer) {
+        (Some(geoip), Some(peer)) if needs_country(rules) => {
+            let trusted_proxies = &state.app_config.trusted_proxies;
+            geoip.country(client_ip(peer.ip(), headers, trusted_proxies))
+        }
+        _ => None,
+    };
+    Visit {
+        platform: headers
+            .get(USER_AGENT)
+            .and_then(|header| header.to_str().ok())
+            .and_then(Platform::from_user_agent),
+        language: accepted_languages(headers).into_iter().next(),
+        country,
+        minute_of_day: minute_of_day(SystemTime::now()),
+    }
+}
+
+fn password_prompt(short_link: &str, status: StatusCode, error: Option<&str>) -> Response {
+    let error = error
+        .map(|error| render(PASSWORD_ERROR_FRAGMENT, &[("error", error)]))
+        .unwrap_or_default();
+    let page = insert_html(PASSWORD_PROMPT_TEMPLATE, "error", &error);
+    let page = render(&page, &[("link", short_link)]);
+    (status, Html(page)).into_response()
+}
+
+/// Shows the page for links that can't be followed, other errors are passed on.
+fn unavailable_link(
+    state: &AppState,
+    service: &ShortenService<'_, '_, NameGenerator>,
+    short_link: &str,
+    error: AppError,
+    names_repo: &mut PostgresRepository,
+) -> Result<Response, AppError> {
+    match error {
+        AppError::NotFoundError | AppError::GoneError(_) => {
+            missing_link(state, service, short_link, &error, names_repo)
+        }
+        // These links aren't missing, so they don't redirect to the fallback page.
+        AppError::UnknownPathError => Ok(status_page(
+            state,
+            short_link,
+            StatusCode::NOT_FOUND,
+            "This path doesn't exist",
+            "This short link exists, but it doesn't lead anywhere with the path after its name.",
+            "",
+        )),
+        AppError::NotYetActiveError(message) => Ok(status_page(
+            state,
+            short_link,
+            StatusCode::FORBIDDEN,
+            "This link isn't active yet",
+            &message,
+            "",
+        )),
+        AppError::DisabledError => {
+            let page = render(DISABLED_LINK_TEMPLATE, &[("link", short_link)]);
+            Ok((StatusCode::GONE, Html(page)).into_response())
+        }
+        e => Err(e),
+    }
+}
+
+/// Tells the visitor that a link doesn't exist (404) or isn't valid anymore (410), or sends
+/// them to the configured fallback URL instead.
+fn missing_link(
+    state: &AppState,
+    service: &ShortenService<'_, '_, NameGenerator>,
+    short_link: &str,
+    error: &AppError,
+    names_repo: &mut PostgresRepository,
+) -> Result<Response, AppError> {
+    let never_existed = matches!(error, AppError::NotFoundError);
+    let suggestion = if state.app_config.suggest_similar_links && never_existed {
+        // The visitor still gets the 404 when no suggestion can be made.
+        service
+            .suggest_name(short_link, names_repo)
+            .unwrap_or_else(|e| {
+                log::warn!("No suggestion could be made for {short_link}: {e:?}");
+                None
+            })
     } else {
-        let missing_uri = state.app_config.base_url.strip_suffix("/s/").unwrap();
-        let missing_uri = missing_uri.to_owned() + "/squish/missing-url";
-        Ok(Redirect::permanent(&missing_uri))
+        None
+    };
+    if let Some(fallback) = &state.app_config.missing_link_redirect {
+        let mut fallback = fallback.clone();
+        if let Some(suggestion) = &suggestion {
+            let suggestion = suggestion.to_string();
+            fallback
+                .query_pairs_mut()
+                .append_pair("suggestion", &suggestion);
+        }
+        return Ok(Redirect::temporary(fallback.as_str()).into_response());
     }
+
+    let (status, title, message) = match error {
+        AppError::GoneError(explanation) => (
+            StatusCode::GONE,
+            "This link is no longer available",
+            explanation.as_str(),
+        ),
+        _ => {
+            let message =
+                "There's no short link with this name. Check whether it was copied correctly.";
+            (StatusCode::NOT_FOUND, "This link doesn't exist", message)
+        }
+    };
+    let suggestion = suggestion
+        .map(|suggestion| {
+            let suggestion = suggestion.to_string();
+            render(SUGGESTION_FRAGMENT, &[("suggestion", &suggestion)])
+        })
+        .unwrap_or_default();
+    Ok(status_page(
+        state,
+        short_link,
+        status,
+        title,
+        message,
+        &suggestion,
+    ))
+}
+
+/// Fills in the missing link template, which also serves other pages about a link's status.
+fn status_page(
+    state: &AppState,
+    short_link: &str,
+    status: StatusCode,
+    title: &str,
+    message: &str,
+    suggestion: &str,
+) -> Response {
+    let page = insert_html(&state.missing_link_template, "suggestion", suggestion);
+    let page = render(
+        &page,
+        &[
+            ("status", status.as_str()),
+            ("title", title),
+            ("message", message),
+            ("link", short_link),
+        ],
+    );
+    (status, Html(page)).into_response()
+}
+
+/// Changes the destination, lifetime or redirect type of a link.
+#[utoipa::path(
+    patch,
+    path = "/s/{short_link}",
+    tag = "links",
+    params(("short_link" = String, Path, description = "The name of the short link")),
+    request_body = LinkEdit,
+    responses(
+        (status = 204, description = "The link was changed"),
+        (status = 400, description = "The changes are invalid", body = String),
+        (status = 401, description = "No management token was supplied"),
+        (status = 403, description = "The management token doesn't belong to this link"),
+        (status = 404, description = "The link doesn't exist", body = String),
+        (status = 410, description = "The link was deleted", body = String),
+    ),
+    security(("management_token" = []))
+)]
+async fn edit_link(
+    state: State<Arc<AppState>>,
+    short_link: Path<String>,
+    headers: HeaderMap,
+    Json(edit): Json<LinkEdit>,
+) -> Result<StatusCode, AppError> {
+    let token = management_token(&headers)?;
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    service.edit_link(&short_link, token, edit, &mut names_repo)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes a link. Its name stays reserved until the tombstone is cleaned up.
+#[utoipa::path(
+    delete,
+    path = "/s/{short_link}",
+    tag = "links",
+    params(("short_link" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 204, description = "The link was deleted"),
+        (status = 401, description = "No management token was supplied"),
+        (status = 403, description = "The management token doesn't belong to this link"),
+        (status = 404, description = "The link doesn't exist", body = String),
+        (status = 410, description = "The link was already deleted", body = String),
+    ),
+    security(("management_token" = []))
+)]
+async fn delete_link(
+    state: State<Arc<AppState>>,
+    short_link: Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let token = management_token(&headers)?;
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    service.delete_link(&short_link, token, &mut names_repo)?;
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::{
+    auth::Caller,
+    config::AppState,
+    errors::AppError,
+    generator::{
+        database::PostgresRepository,
+        moderation::{
+            AdminLink, AuditLogPage, BannedDomainResource, DomainBan, LinkPage, LinkSearch,
+            ModerationService, Pagination, ReportPage, ResolvedReports,
+        },
+    },
+};
+
+/// Checks that the caller holds an admin key and connects to the database on its behalf.
+fn moderation<'a>(
+    state: &'a AppState,
+    caller: &Caller,
+) -> Result<(ModerationService<'a>, PostgresRepository), AppError> {
+    let admin = caller.require_admin()?;
+    let repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    Ok((
+        ModerationService::new(&state.app_config.base_url, admin.id),
+        repo,
+    ))
+}
+
+/// Searches all links, including expired, deleted and disabled ones.
+#[utoipa::path(
+    get,
+    path = "/admin/links",
+    tag = "admin",
+    params(LinkSearch),
+    responses(
+        (status = 200, description = "One page of matching links, newest first", body = LinkPage),
+        (status = 400, description = "One of the filters is invalid", body = String),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn search_links(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    Query(search): Query<LinkSearch>,
+) -> Result<Json<LinkPage>, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    Ok(Json(service.search_links(search, &mut repo)?))
+}
+
+/// Keeps a link from being followed until it's enabled again.
+#[utoipa::path(
+    post,
+    path = "/admin/links/{code}/disable",
+    tag = "admin",
+    params(("code" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 200, description = "The link was disabled", body = AdminLink),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+        (status = 404, description = "The link doesn't exist", body = String),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn disable_link(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    code: Path<String>,
+) -> Result<Json<AdminLink>, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    Ok(Json(service.set_disabled(&code, true, &mut repo)?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/links/{code}/enable",
+    tag = "admin",
+    params(("code" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 200, description = "The link was enabled", body = AdminLink),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+        (status = 404, description = "The link doesn't exist", body = String),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn enable_link(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    code: Path<String>,
+) -> Result<Json<AdminLink>, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    Ok(Json(service.set_disabled(&code, false, &mut repo)?))
+}
+
+/// Takes a link down for good. Like a deletion by its owner a tombstone is kept, so the name
+/// can't be taken again until it's cleaned up, and visitors see that the link was disabled.
+#[utoipa::path(
+    delete,
+    path = "/admin/links/{code}",
+    tag = "admin",
+    params(("code" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 204, description = "The link was removed"),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+        (status = 404, description = "The link doesn't exist", body = String),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_link(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    code: Path<String>,
+) -> Result<StatusCode, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    service.delete_link(&code, &mut repo)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/banned-domains",
+    tag = "admin",
+    responses(
+        (status = 200, description = "All banned domains", body = Vec<BannedDomainResource>),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn banned_domains(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<BannedDomainResource>>, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    Ok(Json(service.banned_domains(&mut repo)?))
+}
+
+/// Bans a domain and its subdomains. New links to it are rejected and the existing ones are
+/// disabled. Banning a domain again updates the reason.
+#[utoipa::path(
+    post,
+    path = "/admin/banned-domains",
+    tag = "admin",
+    request_body = DomainBan,
+    responses(
+        (status = 201, description = "The domain was banned", body = BannedDomainResource),
+        (status = 400, description = "The domain is invalid", body = String),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn ban_domain(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    Json(ban): Json<DomainBan>,
+) -> Result<(StatusCode, Json<BannedDomainResource>), AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    let banned = service.ban_domain(ban, &mut repo)?;
+    Ok((StatusCode::CREATED, Json(banned)))
+}
+
+/// Lifts a ban. Links that were disabled by it stay disabled.
+#[utoipa::path(
+    delete,
+    path = "/admin/banned-domains/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "The banned domain")),
+    responses(
+        (status = 204, description = "The ban was lifted"),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+        (status = 404, description = "The domain isn't banned", body = String),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn unban_domain(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    domain: Path<String>,
+) -> Result<StatusCode, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    service.unban_domain(&domain, &mut repo)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lists the reports that haven't been resolved yet, newest first.
+#[utoipa::path(
+    get,
+    path = "/admin/reports",
+    tag = "admin",
+    params(Pagination),
+    responses(
+        (status = 200, description = "One page of open reports", body = ReportPage),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn open_reports(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<ReportPage>, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    Ok(Json(service.open_reports(pagination, &mut repo)?))
+}
+
+/// Dismisses the open reports about a link. Enabling a link does this as well.
+#[utoipa::path(
+    post,
+    path = "/admin/links/{code}/reports/resolve",
+    tag = "admin",
+    params(("code" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 200, description = "The reports were resolved", body = ResolvedReports),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+        (status = 404, description = "The link doesn't exist", body = String),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn resolve_reports(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    code: Path<String>,
+) -> Result<Json<ResolvedReports>, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    Ok(Json(service.resolve_reports(&code, &mut repo)?))
+}
+
+/// Lists the moderation actions taken so far, newest first.
+#[utoipa::path(
+    get,
+    path = "/admin/audit-log",
+    tag = "admin",
+    params(Pagination),
+    responses(
+        (status = 200, description = "One page of the audit log", body = AuditLogPage),
+        (status = 401, description = "No valid API key was supplied"),
+        (status = 403, description = "The API key isn't an admin key"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn audit_log(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<AuditLogPage>, AppError> {
+    let (service, mut repo) = moderation(&state, &caller)?;
+    Ok(Json(service.audit_log(pagination, &mut repo)?))
+}
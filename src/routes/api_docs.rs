@@ -0,0 +1,127 @@
+use axum::{response::Html, Json};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use super::MANAGEMENT_TOKEN_HEADER;
+use crate::pages::API_DOCS_TEMPLATE;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        super::shorten,
+        super::list_links,
+        super::shorten_bulk,
+        super::retrieve_original_link,
+        super::unlock_link,
+        super::edit_link,
+        super::delete_link,
+        super::link_info,
+        super::link_qr,
+        super::report_link,
+        super::v1::create_link,
+        super::v1::list_links,
+        super::v1::get_link,
+        super::v1::delete_link,
+        super::admin::search_links,
+        super::admin::disable_link,
+        super::admin::enable_link,
+        super::admin::delete_link,
+        super::admin::banned_domains,
+        super::admin::ban_domain,
+        super::admin::unban_domain,
+        super::admin::open_reports,
+        super::admin::resolve_reports,
+        super::admin::audit_log,
+    ),
+    components(schemas(
+        super::InputLink,
+        super::FollowForm,
+        crate::generator::shorten::OutputLink,
+        crate::generator::shorten::OwnedLink,
+        crate::generator::shorten::BulkLink,
+        crate::generator::shorten::BulkResult,
+        crate::generator::shorten::LinkEdit,
+        crate::generator::shorten::LinkInfo,
+        crate::generator::shorten::LinkResource,
+        crate::generator::shorten::LinkManagement,
+        crate::generator::database::RedirectType,
+        crate::generator::routing::RoutingRule,
+        crate::generator::routing::Platform,
+        crate::generator::routing::Variant,
+        crate::generator::shorten::VariantStats,
+        crate::generator::moderation::AdminLink,
+        crate::generator::moderation::LinkStatus,
+        crate::generator::moderation::LinkPage,
+        crate::generator::moderation::DomainBan,
+        crate::generator::moderation::BannedDomainResource,
+        crate::generator::moderation::ReportReason,
+        crate::generator::moderation::ReportInput,
+        crate::generator::moderation::AdminReport,
+        crate::generator::moderation::ReportPage,
+        crate::generator::moderation::ResolvedReports,
+        crate::generator::moderation::AuditLogEntry,
+        crate::generator::moderation::AuditLogPage,
+        crate::qr::QrFormat,
+        crate::qr::ErrorCorrection,
+    )),
+    info(
+        title = "SquishLink",
+        description = "Shortens links into memorable names like `Aardvarkable123`."
+    ),
+    modifiers(&ApiDocAdditions),
+    tags(
+        (name = "v1", description = "Create, look up, list and delete links"),
+        (name = "links", description = "The original routes, kept working for existing clients"),
+        (name = "admin", description = "Search and moderate links, requires an admin key")
+    )
+)]
+pub struct ApiDoc;
+
+/// Adds what the derive can't express: the security schemes, and leaving out the license
+/// because the crate doesn't declare one.
+struct ApiDocAdditions;
+
+impl Modify for ApiDocAdditions {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let api_key = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build();
+        components.add_security_scheme("api_key", SecurityScheme::Http(api_key));
+        let management_token = ApiKey::Header(ApiKeyValue::new(MANAGEMENT_TOKEN_HEADER));
+        components
+            .add_security_scheme("management_token", SecurityScheme::ApiKey(management_token));
+    }
+}
+
+pub async fn openapi_spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+pub async fn api_docs_page() -> Html<&'static str> {
+    Html(API_DOCS_TEMPLATE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    const SPEC_PATH: &str = "openapi.json";
+
+    /// The committed spec is what API clients are generated from, so it must match the code.
+    /// Run `UPDATE_OPENAPI=1 cargo test` after changing the API to update it.
+    #[test]
+    fn test_openapi_spec_is_up_to_date() {
+        let generated = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if env::var("UPDATE_OPENAPI").is_ok() {
+            fs::write(SPEC_PATH, &generated).unwrap();
+        }
+        let committed = fs::read_to_string(SPEC_PATH).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "{SPEC_PATH} is outdated, run `UPDATE_OPENAPI=1 cargo test` to update it."
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequest, Request},
+    http::header::CONTENT_TYPE,
+    Form, Json,
+};
+use serde::{Deserialize, Deserializer};
+use url::form_urlencoded;
+
+use super::InputLink;
+use crate::errors::AppError;
+
+/// The body of a shorten request. Besides JSON, HTML forms and curl's `-d link=...` send form
+/// encoded bodies, while a `text/plain` body holds nothing but the link.
+pub struct ShortenInput(pub InputLink);
+
+#[async_trait]
+impl<S> FromRequest<S> for ShortenInput
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.split(';').next())
+            .map(|mime| mime.trim().to_lowercase())
+            .unwrap_or_default();
+        match content_type.as_str() {
+            "application/json" => {
+                let Json(input) = Json::<InputLink>::from_request(req, state)
+                    .await
+                    .map_err(|rejection| rejected_body(&rejection.body_text()))?;
+                Ok(Self(input))
+            }
+            "application/x-www-form-urlencoded" => {
+                let (parts, body) = req.into_parts();
+                let body = Bytes::from_request(Request::from_parts(parts.clone(), body), state)
+                    .await
+                    .map_err(|rejection| rejected_body(&rejection.body_text()))?;
+                let req = Request::from_parts(parts, Body::from(without_blank_fields(&body)));
+                let Form(input) = Form::<InputLink>::from_request(req, state)
+                    .await
+                    .map_err(|rejection| rejected_body(&rejection.body_text()))?;
+                Ok(Self(input))
+            }
+            "text/plain" => {
+                let body = String::from_request(req, state)
+                    .await
+                    .map_err(|rejection| rejected_body(&rejection.body_text()))?;
+                let link = body.trim();
+                if link.is_empty() {
+                    return Err(invalid_body("the link is missing"));
+                }
+                Ok(Self(InputLink {
+                    link: link.to_string(),
+                    ..Default::default()
+                }))
+            }
+            _ => Err(AppError::UnsupportedMediaTypeError),
+        }
+    }
+}
+
+/// HTML forms send the fields that were left blank as empty strings, those are left out so
+/// they count as missing, e.g. an empty `ttl` gets the default lifetime.
+fn without_blank_fields(body: &[u8]) -> String {
+    let fields = form_urlencoded::parse(body).filter(|(_, value)| !value.is_empty());
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(fields)
+        .finish()
+}
+
+/// A flag that can also be set by a checked HTML checkbox, which sends `on` unless the form
+/// gives it another value.
+pub fn checkbox<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Text(String),
+    }
+    match Flag::deserialize(deserializer)? {
+        Flag::Bool(value) => Ok(value),
+        Flag::Text(text) => match text.as_str() {
+            "true" | "on" | "1" => Ok(true),
+            "false" | "off" | "0" => Ok(false),
+            _ => Err(serde::de::Error::custom(format!(
+                "'{text}' isn't true or false"
+            ))),
+        },
+    }
+}
+
+fn invalid_body(reason: &str) -> AppError {
+    AppError::UserInputError(format!("The request body is invalid: {reason}"))
+}
+
+/// The messages of the extractors and serde name Rust types and parser internals, so they're
+/// only logged and the client gets a generic message.
+fn rejected_body(detail: &str) -> AppError {
+    log::debug!("A shorten request body was rejected: {detail}");
+    invalid_body("send a link, optionally with the fields described in the API docs")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    async fn extract(content_type: &str, body: &str) -> Result<InputLink, AppError> {
+        let request = Request::post("/s")
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        ShortenInput::from_request(request, &())
+            .await
+            .map(|ShortenInput(input)| input)
+    }
+
+    #[tokio::test]
+    async fn test_json_body() {
+        let input = extract("application/json", r#"{"link": "example.com", "ttl": 60}"#)
+            .await
+            .unwrap();
+        assert_eq!(input.link, "example.com");
+        assert_eq!(input.ttl, Some(60));
+        let input = extract("application/json", r#"{"link": "example.com", "qr": true}"#)
+            .await
+            .unwrap();
+        assert!(input.qr);
+    }
+
+    #[tokio::test]
+    async fn test_form_body() {
+        let content_type = "application/x-www-form-urlencoded; charset=utf-8";
+        let input = extract(content_type, "url=example.com%2Fa%3Fb%3Dc&alias=")
+            .await
+            .unwrap();
+        assert_eq!(input.link, "example.com/a?b=c");
+        assert_eq!(input.alias, None);
+    }
+
+    #[tokio::test]
+    async fn test_form_body_from_html_form() {
+        let content_type = "application/x-www-form-urlencoded";
+        let body = "link=example.com&ttl=&max_visits=&qr=on&forward_path=false";
+        let input = extract(content_type, body).await.unwrap();
+        assert_eq!(input.ttl, None);
+        assert_eq!(input.max_visits, None);
+        assert!(input.qr);
+        assert!(!input.interstitial);
+        assert!(!input.forward_path);
+        let input = extract(content_type, "link=example.com&ttl=60&qr=true")
+            .await
+            .unwrap();
+        assert_eq!(input.ttl, Some(60));
+        assert!(input.qr);
+        let result = extract(content_type, "link=example.com&qr=maybe").await;
+        assert!(matches!(result, Err(AppError::UserInputError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_plain_text_body() {
+        let input = extract("text/plain", "  example.com\n").await.unwrap();
+        assert_eq!(input.link, "example.com");
+        let result = extract("text/plain", " \n").await;
+        assert!(matches!(result, Err(AppError::UserInputError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_bodies_are_rejected() {
+        let result = extract("application/json", "{\"link\": ").await;
+        assert!(matches!(result, Err(AppError::UserInputError(_))));
+        let result = extract("application/xml", "<link/>").await;
+        assert!(matches!(result, Err(AppError::UnsupportedMediaTypeError)));
+    }
+}
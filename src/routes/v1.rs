@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use rand::thread_rng;
+
+use super::{make_service, management_token, optional_management_token, InputLink};
+use crate::{
+    auth::Caller,
+    config::AppState,
+    errors::AppError,
+    generator::{
+        database::PostgresRepository,
+        shorten::{LinkResource, Shortener, Viewer},
+    },
+    qr::svg_data_uri,
+};
+
+/// Shortens a link and returns the complete link, including its management token.
+#[utoipa::path(
+    post,
+    path = "/api/v1/links",
+    tag = "v1",
+    request_body = InputLink,
+    responses(
+        (status = 201, description = "The link was created", body = LinkResource),
+        (status = 400, description = "The link or one of the options is invalid", body = String),
+        (status = 401, description = "An API key is required or the supplied key is invalid"),
+        (status = 429, description = "The rate limit or the daily quota of the API key was exceeded"),
+    ),
+    security((), ("api_key" = []))
+)]
+pub(super) async fn create_link(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    headers: HeaderMap,
+    Json(input): Json<InputLink>,
+) -> Result<(StatusCode, Json<LinkResource>), AppError> {
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let require_api_key = state.app_config.require_api_key;
+    let api_key_id = caller.authorize_shorten(require_api_key)?;
+    let options = input.options(&headers, api_key_id);
+    let mut link = input.link.clone();
+    let service = make_service(&state);
+    let mut rng = thread_rng();
+    let mut resource = service.create_link(&mut link, &options, &mut names_repo, &mut rng)?;
+    if input.qr {
+        resource.qr = Some(svg_data_uri(&resource.short_url)?);
+    }
+    Ok((StatusCode::CREATED, Json(resource)))
+}
+
+/// Lists the links created with the caller's API key.
+#[utoipa::path(
+    get,
+    path = "/api/v1/links",
+    tag = "v1",
+    responses(
+        (status = 200, description = "The links of the API key, newest first", body = Vec<LinkResource>),
+        (status = 401, description = "No valid API key was supplied"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_links(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<LinkResource>>, AppError> {
+    let api_key = caller.require_key()?;
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let resources = service.resources_for_key(api_key, &mut names_repo)?;
+    Ok(Json(resources))
+}
+
+/// Looks up a link without following it. The remaining visits and the variants with their
+/// visits are only shown to the owner, who sends the API key the link was created with or its
+/// management token. The owner also sees links that are scheduled, expired or protected.
+#[utoipa::path(
+    get,
+    path = "/api/v1/links/{code}",
+    tag = "v1",
+    params(("code" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 200, description = "The link", body = LinkResource),
+        (status = 401, description = "The link is protected by a password and the caller isn't its owner, or the supplied API key is invalid", body = String),
+        (status = 403, description = "The link isn't active yet and the caller isn't its owner", body = String),
+        (status = 404, description = "The link doesn't exist", body = String),
+        (status = 410, description = "The link was deleted, or expired and the caller isn't its owner", body = String),
+    ),
+    security((), ("api_key" = []), ("management_token" = []))
+)]
+pub(super) async fn get_link(
+    state: State<Arc<AppState>>,
+    caller: Caller,
+    code: Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<LinkResource>, AppError> {
+    let viewer = Viewer {
+        api_key_id: caller.0.map(|api_key| api_key.id),
+        management_token: optional_management_token(&headers),
+    };
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    let resource = service.get_link(&code, &viewer, &mut names_repo)?;
+    Ok(Json(resource))
+}
+
+/// Deletes a link. Its name stays reserved until the tombstone is cleaned up.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/links/{code}",
+    tag = "v1",
+    params(("code" = String, Path, description = "The name of the short link")),
+    responses(
+        (status = 204, description = "The link was deleted"),
+        (status = 401, description = "No management token was supplied"),
+        (status = 403, description = "The management token doesn't belong to this link"),
+        (status = 404, description = "The link doesn't exist", body = String),
+        (status = 410, description = "The link was already deleted", body = String),
+    ),
+    security(("management_token" = []))
+)]
+pub(super) async fn delete_link(
+    state: State<Arc<AppState>>,
+    code: Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let token = management_token(&headers)?;
+    let service = make_service(&state);
+    let mut names_repo = PostgresRepository::from_config(&state.app_config.db_config)?;
+    service.delete_link(&code, token, &mut names_repo)?;
+    Ok(StatusCode::NO_CONTENT)
+}
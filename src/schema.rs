@@ -1,10 +1,111 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        name -> Text,
+        key_hash -> Text,
+        daily_quota -> Nullable<Int4>,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        is_admin -> Bool,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Int4,
+        api_key_id -> Nullable<Int4>,
+        action -> Text,
+        target -> Text,
+        details -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    banned_domains (domain) {
+        domain -> Text,
+        reason -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    link_reports (id) {
+        id -> Int4,
+        link_id -> Int4,
+        reason -> Text,
+        details -> Nullable<Text>,
+        reporter_hash -> Text,
+        reporter_agent -> Nullable<Text>,
+        api_key_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+        resolved_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    link_rules (id) {
+        id -> Int4,
+        link_id -> Int4,
+        position -> Int4,
+        platform -> Nullable<Text>,
+        language -> Nullable<Text>,
+        country -> Nullable<Text>,
+        starts_at_minute -> Nullable<Int4>,
+        ends_at_minute -> Nullable<Int4>,
+        destination -> Text,
+    }
+}
+
+diesel::table! {
+    link_variants (id) {
+        id -> Int4,
+        link_id -> Int4,
+        position -> Int4,
+        destination -> Text,
+        weight -> Int4,
+        visit_count -> Int8,
+    }
+}
+
 diesel::table! {
     links (id) {
         id -> Int4,
         original_link -> Text,
         short_link -> Text,
         created_at -> Timestamp,
+        management_token_hash -> Nullable<Text>,
+        expires_at -> Timestamp,
+        redirect_type -> Text,
+        api_key_id -> Nullable<Int4>,
+        visit_count -> Int8,
+        deleted_at -> Nullable<Timestamp>,
+        disabled_at -> Nullable<Timestamp>,
+        interstitial -> Bool,
+        password_hash -> Nullable<Text>,
+        remaining_visits -> Nullable<Int4>,
+        not_before -> Nullable<Timestamp>,
+        sticky_variants -> Bool,
+        forward_query -> Bool,
+        forward_path -> Bool,
     }
 }
+
+diesel::joinable!(audit_log -> api_keys (api_key_id));
+diesel::joinable!(link_reports -> api_keys (api_key_id));
+diesel::joinable!(link_reports -> links (link_id));
+diesel::joinable!(link_rules -> links (link_id));
+diesel::joinable!(link_variants -> links (link_id));
+diesel::joinable!(links -> api_keys (api_key_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
+    banned_domains,
+    link_reports,
+    link_rules,
+    link_variants,
+    links,
+);